@@ -2,11 +2,26 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use clap::{CommandFactory, Parser, Subcommand};
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Input;
-use graphlog_proto::types::common::{AnchorType, ClaimType, ClientConfig, Config, Key, KeyType};
+use graphlog_proto::types::common::{
+    id_equal, AnchorType, ClaimType, ClientConfig, Config, Decodable, Indexable, Key, KeyType,
+};
+use graphlog_proto::types::endorsement::Endorsement;
+use graphlog_proto::types::graph::{GraphFormat, TrustEdge, TrustGraph};
+use graphlog_proto::types::merkle::{Hash, MerkleTree, SignedTreeHead};
+use graphlog_proto::types::policy::{TrustPolicy, Verdict};
 use graphlog_proto::types::reid::Reid;
-use graphlog_proto::utils::http_server::ReidMessage;
+use graphlog_proto::types::revocation::EndorsementRevocation;
+use graphlog_proto::types::storage::record_leaf_hash;
+use graphlog_proto::utils::http_server::{
+    EndorsementMessage, HistoryMessage, InclusionProofMessage, LogKeyMessage, PowMessage,
+    RangeMessage, ReachMessage, ReidMessage, RevocationMessage, SearchMessage,
+};
+use graphlog_proto::utils::keys::key_id;
+use openssl::base64::{decode_block, encode_block};
 use openssl::pkey::{Id, PKey, Private, Public};
 use reqwest::{Client, StatusCode};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
 use std::panic;
 use std::{
     env, fs,
@@ -59,16 +74,146 @@ enum Commands {
         #[arg(short, long)]
         log_addr: Option<String>,
     },
+    /// Show every version of a reid and what changed between them
+    History {
+        /// base64 id, defaults to our own
+        #[arg(short, long)]
+        id: Option<String>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Find the ids that currently assert an anchor or claim a key
+    Search {
+        #[arg(value_enum, long, requires = "anchor_value")]
+        anchor_type: Option<AnchorType>,
+        #[arg(long, requires = "anchor_type")]
+        anchor_value: Option<String>,
+        /// file holding the claimed key, e.g. an ssh public key
+        #[arg(long, required_unless_present = "anchor_type")]
+        claim_key_path: Option<PathBuf>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
     /// Get the last entry of a log
     GetTail {
         #[arg(short, long)]
         log_addr: Option<String>,
     },
+    /// Follow the log live, printing each reid as it is committed
+    Watch {
+        /// index to start from, defaults to only new entries
+        #[arg(short, long)]
+        start: Option<u64>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Watch the log for changes to our identity we didn't make
+    Monitor {
+        /// index to start checking from, defaults to where the last run stopped
+        #[arg(short, long)]
+        start: Option<u64>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Page through the reids in a log by index
+    GetRange {
+        #[arg(short, long, default_value_t = 0)]
+        start: u64,
+        /// most entries per page, the log caps this
+        #[arg(long)]
+        limit: Option<usize>,
+        /// keep fetching pages until the end of the log
+        #[arg(short, long)]
+        all: bool,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Endorse the claims of another identity's reid
+    Endorse {
+        /// base64 id of the reid to endorse
+        #[arg(short, long)]
+        id: String,
+        /// only endorse claims of this type
+        #[arg(value_enum, long)]
+        claim_type: Option<ClaimType>,
+        /// defaults to the expiration in graphlog.toml
+        #[arg(short, long)]
+        expiration: Option<String>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// List the unexpired endorsements of an identity
+    Endorsers {
+        /// base64 id, defaults to our own
+        #[arg(short, long)]
+        id: Option<String>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Find the shortest chain of endorsements from one identity to another
+    TrustPath {
+        /// base64 id at the end of the chain
+        #[arg(short, long)]
+        to: String,
+        /// base64 id at the start of the chain, defaults to our own
+        #[arg(short, long)]
+        from: Option<String>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// List every identity within some number of endorsements of the trust anchors
+    Reach {
+        /// base64 ids to start from, defaults to our own
+        #[arg(short, long)]
+        roots: Vec<String>,
+        #[arg(long, default_value_t = 2)]
+        hops: usize,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Export the endorsement graph for visualization
+    ExportGraph {
+        #[arg(value_enum, short, long, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// file to write to, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Decide whether to trust an identity using a policy from graphlog.toml
+    Evaluate {
+        /// base64 id to evaluate
+        #[arg(short, long)]
+        id: String,
+        /// name of the [policies.<name>] table to apply
+        #[arg(short, long, default_value = "default")]
+        policy: String,
+        /// evaluate as of this time instead of now
+        #[arg(long)]
+        at: Option<String>,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
+    /// Withdraw our endorsements of another identity
+    RevokeEndorsement {
+        /// base64 id whose endorsements to withdraw
+        #[arg(short, long)]
+        id: String,
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
     /// Mark Reid entry as revoked
     Revoke {
         #[arg(short, long)]
         log_addr: Option<String>,
     },
+    /// Check the key the log signs tree heads with, pinning it on first use
+    /// and following rotations signed by the pinned key
+    LogKey {
+        #[arg(short, long)]
+        log_addr: Option<String>,
+    },
     // TODO add tail_num
 }
 
@@ -181,8 +326,11 @@ fn gen_config_file(graphlog_dir: &Path) -> (Config, PathBuf) {
             expiration,
             claims: None,
             anchors: None,
+            seq: 0,
+            log_key: None,
         }),
         server_conf: None,
+        policies: None,
     };
 
     save_config(&config, &config_path);
@@ -243,12 +391,16 @@ async fn parse_and_execute(
     config_path: PathBuf,
 ) {
     // TODO eventually load all of the reid anchor and claims from the config file here
-    let client_config: ClientConfig = config.client_conf.unwrap();
+    let mut client_config: ClientConfig = config.client_conf.unwrap();
     let claims: Option<Vec<(ClaimType, Key)>> = client_config.claims.clone();
     let anchors: Option<Vec<(AnchorType, String)>> = client_config.anchors.clone();
     let expiration: DateTime<Utc> = client_config.expiration;
-    let mut reid: Reid =
-        Reid::new_with_keys(&pub_key, &prv_key, expiration, None, claims, anchors, false);
+    // Every record we publish has to carry a higher seq than the last one
+    // the log accepted for our id, otherwise it is rejected as a replay
+    let seq: u64 = client_config.seq + 1;
+    let mut reid: Reid = Reid::new_with_keys(
+        &pub_key, &prv_key, expiration, None, claims, anchors, false, seq,
+    );
     match cli.command {
         Some(Commands::Publish { log_addr }) => {
             // get pem_str
@@ -261,10 +413,15 @@ async fn parse_and_execute(
                 Ok(str) => str,
             };
 
-            if let Some(log_addr) = log_addr {
-                publish_reid(log_addr, reid, pem_str).await;
-            } else {
-                publish_reid(client_config.log_addr, reid, pem_str).await;
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            if publish_reid(log_addr, reid, pem_str, &prv_key, &config_path).await {
+                client_config.seq = seq;
+                let new_config = Config {
+                    server_conf: config.server_conf,
+                    policies: config.policies,
+                    client_conf: Some(client_config),
+                };
+                save_config(&new_config, &config_path);
             }
         }
         Some(Commands::AppendClaim {
@@ -291,7 +448,7 @@ async fn parse_and_execute(
             if let Err(why) = pubk_file.read_to_end(&mut pubk_raw) {
                 panic!("Error reading public key file: {why}");
             };
-            let claim_key_str  = match String::from_utf8(pubk_raw) {
+            let claim_key_str = match String::from_utf8(pubk_raw) {
                 Err(why) => panic!("Couldn't convert read key to string: {why}"),
                 Ok(str) => str,
             };
@@ -304,9 +461,15 @@ async fn parse_and_execute(
                 client_config,
                 publish.unwrap_or_default(),
                 pem_str,
+                &prv_key,
+                &config_path,
             )
             .await;
-            let new_config = Config { server_conf: config.server_conf, client_conf: Some(client_config) };
+            let new_config = Config {
+                server_conf: config.server_conf,
+                policies: config.policies,
+                client_conf: Some(client_config),
+            };
             save_config(&new_config, &config_path);
         }
         Some(Commands::AppendAnchor {
@@ -329,17 +492,51 @@ async fn parse_and_execute(
                 client_config,
                 publish.unwrap_or_default(),
                 pem_str,
+                &prv_key,
+                &config_path,
             )
             .await;
-            let new_config = Config { server_conf: config.server_conf, client_conf: Some(client_config) };
+            let new_config = Config {
+                server_conf: config.server_conf,
+                policies: config.policies,
+                client_conf: Some(client_config),
+            };
             save_config(&new_config, &config_path);
         }
         Some(Commands::LookupReid { id, log_addr }) => {
-            if let Some(log_addr) = log_addr {
-                look_up_reid(log_addr, id).await;
-            } else {
-                look_up_reid(client_config.log_addr, id).await;
-            }
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            look_up_reid(log_addr, id, &log_key).await;
+        }
+        Some(Commands::History { id, log_addr }) => {
+            let id: String = id.unwrap_or(encode_block(&reid.get_id()));
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            get_history(log_addr, id, &log_key).await;
+        }
+        Some(Commands::Search {
+            anchor_type,
+            anchor_value,
+            claim_key_path,
+            log_addr,
+        }) => {
+            let claim_key: Option<String> =
+                claim_key_path.map(|path| match fs::read_to_string(&path) {
+                    Err(why) => panic!("Couldn't read claim key file {}: {why}", path.display()),
+                    Ok(claim_key) => claim_key,
+                });
+            let search_msg = SearchMessage {
+                anchor: anchor_type.zip(anchor_value),
+                claim_key,
+            };
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            search_reids(log_addr, search_msg).await;
         }
         Some(Commands::GetTail { log_addr }) => {
             if let Some(log_addr) = log_addr {
@@ -348,8 +545,124 @@ async fn parse_and_execute(
                 get_tail(client_config.log_addr).await;
             }
         }
+        Some(Commands::Watch { start, log_addr }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            watch_log(log_addr, start, &log_key).await;
+        }
+        Some(Commands::Monitor { start, log_addr }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            monitor_identity(log_addr, start, &pub_key, &config_path, &log_key).await;
+        }
+        Some(Commands::GetRange {
+            start,
+            limit,
+            all,
+            log_addr,
+        }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            get_range(log_addr, start, limit, all, &log_key).await;
+        }
+        Some(Commands::Endorse {
+            id,
+            claim_type,
+            expiration,
+            log_addr,
+        }) => {
+            let expiration: DateTime<Utc> = match expiration {
+                None => client_config.expiration,
+                Some(expiration_str) => match _parse_datetime(&expiration_str) {
+                    Some(dt) => dt,
+                    None => panic!("Failed to read date input"),
+                },
+            };
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            let log_key: PKey<Public> = match trusted_log_key(&log_addr, &client_config).await {
+                None => return,
+                Some(log_key) => log_key,
+            };
+            endorse_reid(
+                log_addr, id, claim_type, expiration, &pub_key, &prv_key, &log_key,
+            )
+            .await;
+        }
+        Some(Commands::Endorsers { id, log_addr }) => {
+            let id: String = id.unwrap_or(encode_block(&reid.get_id()));
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            get_endorsers(log_addr, id).await;
+        }
+        Some(Commands::TrustPath { to, from, log_addr }) => {
+            let from: String = from.unwrap_or(encode_block(&reid.get_id()));
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            get_trust_path(log_addr, from, to).await;
+        }
+        Some(Commands::Reach {
+            mut roots,
+            hops,
+            log_addr,
+        }) => {
+            if roots.is_empty() {
+                roots.push(encode_block(&reid.get_id()));
+            }
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            get_reach(log_addr, roots, hops).await;
+        }
+        Some(Commands::ExportGraph {
+            format,
+            output,
+            log_addr,
+        }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            export_graph(log_addr, format, output).await;
+        }
+        Some(Commands::Evaluate {
+            id,
+            policy,
+            at,
+            log_addr,
+        }) => {
+            let at: DateTime<Utc> = match at {
+                None => Utc::now(),
+                Some(at_str) => match _parse_datetime(&at_str) {
+                    Some(dt) => dt,
+                    None => panic!("Failed to read date input"),
+                },
+            };
+            let trust_policy: TrustPolicy = match config
+                .policies
+                .as_ref()
+                .and_then(|policies| policies.get(&policy))
+            {
+                None => {
+                    println!("No policy named {policy} in graphlog.toml");
+                    return;
+                }
+                Some(trust_policy) => trust_policy.clone(),
+            };
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            if let Some(verdict) = evaluate_policy(log_addr, id, &trust_policy, at).await {
+                print!("{verdict}");
+            }
+        }
+        Some(Commands::RevokeEndorsement { id, log_addr }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            revoke_endorsements(log_addr, id, &pub_key, &prv_key).await;
+        }
         Some(Commands::Revoke { log_addr }) => {
-            reid.revoke();
+            if let Err(why) = reid.revoke(&prv_key) {
+                panic!("Signing revoked Reid failed: {why}");
+            }
             let pem_vec: Vec<u8> = match pub_key.public_key_to_pem() {
                 Err(why) => panic!("Could not convert pub_key to pem format: {why}"),
                 Ok(vec) => vec,
@@ -359,10 +672,28 @@ async fn parse_and_execute(
                 Ok(str) => str,
             };
 
-            if let Some(log_addr) = log_addr {
-                publish_reid(log_addr, reid, pem_str).await
-            } else {
-                publish_reid(client_config.log_addr, reid, pem_str).await
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            if publish_reid(log_addr, reid, pem_str, &prv_key, &config_path).await {
+                client_config.seq = seq;
+                let new_config = Config {
+                    server_conf: config.server_conf,
+                    policies: config.policies,
+                    client_conf: Some(client_config),
+                };
+                save_config(&new_config, &config_path);
+            }
+        }
+        Some(Commands::LogKey { log_addr }) => {
+            let log_addr: String = log_addr.unwrap_or(client_config.log_addr.clone());
+            if let Some(log_key) = check_log_key(&log_addr, client_config.log_key.as_deref()).await
+            {
+                client_config.log_key = Some(log_key);
+                let new_config = Config {
+                    server_conf: config.server_conf,
+                    policies: config.policies,
+                    client_conf: Some(client_config),
+                };
+                save_config(&new_config, &config_path);
             }
         }
         None => {
@@ -372,6 +703,25 @@ async fn parse_and_execute(
     }
 }
 
+fn pub_key_pem(pub_key: &PKey<Public>) -> String {
+    let pem_vec: Vec<u8> = match pub_key.public_key_to_pem() {
+        Err(why) => panic!("Could not convert pub_key to pem format: {why}"),
+        Ok(vec) => vec,
+    };
+    match String::from_utf8(pem_vec) {
+        Err(why) => panic!("Couldn't convert pem vec to string: {why}"),
+        Ok(str) => str,
+    }
+}
+
+// base64 ids may contain '/' and '+' which can't appear raw in a path segment
+fn url_encode_id(id_b64: &str) -> String {
+    id_b64
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D")
+}
+
 fn _parse_datetime(input: &str) -> Option<DateTime<Utc>> {
     let formats = [
         "%Y-%m-%d %H:%M:%S",
@@ -390,6 +740,7 @@ fn _parse_datetime(input: &str) -> Option<DateTime<Utc>> {
     None
 }
 
+#[allow(clippy::too_many_arguments, clippy::unnecessary_unwrap)]
 async fn append_claim(
     claim_type: ClaimType,
     claim_value: Key,
@@ -397,6 +748,8 @@ async fn append_claim(
     mut client_config: ClientConfig,
     publish: bool,
     pem_str: String,
+    prv_key: &PKey<Private>,
+    config_path: &Path,
 ) -> ClientConfig {
     reid.append_claim(claim_type.clone(), claim_value.clone());
     let log_addr: String = client_config.log_addr.clone();
@@ -412,12 +765,19 @@ async fn append_claim(
     }
 
     if publish {
-        publish_reid(log_addr, reid, pem_str).await;
+        if let Err(why) = reid.update_sig(prv_key) {
+            panic!("Signing Reid failed: {why}");
+        }
+        let seq: u64 = reid.get_seq();
+        if publish_reid(log_addr, reid, pem_str, prv_key, config_path).await {
+            client_config.seq = seq;
+        }
     }
 
     client_config
 }
 
+#[allow(clippy::too_many_arguments, clippy::unnecessary_unwrap)]
 async fn append_anchor(
     anchor_type: AnchorType,
     anchor_value: String,
@@ -425,6 +785,8 @@ async fn append_anchor(
     mut client_config: ClientConfig,
     publish: bool,
     pem_str: String,
+    prv_key: &PKey<Private>,
+    config_path: &Path,
 ) -> ClientConfig {
     reid.append_anchor(anchor_type.clone(), anchor_value.clone());
     let log_addr: String = client_config.log_addr.clone();
@@ -440,14 +802,38 @@ async fn append_anchor(
     }
 
     if publish {
-        publish_reid(log_addr, reid, pem_str).await;
+        if let Err(why) = reid.update_sig(prv_key) {
+            panic!("Signing Reid failed: {why}");
+        }
+        let seq: u64 = reid.get_seq();
+        if publish_reid(log_addr, reid, pem_str, prv_key, config_path).await {
+            client_config.seq = seq;
+        }
     }
 
     client_config
 }
 
-async fn publish_reid(log_addr: String, reid: Reid, pem_str: String) {
+// Returns true if the log accepted the reid
+async fn publish_reid(
+    log_addr: String,
+    mut reid: Reid,
+    pem_str: String,
+    prv_key: &PKey<Private>,
+    config_path: &Path,
+) -> bool {
     let client = Client::new();
+    let difficulty: u8 = match get_pow_difficulty(&client, &log_addr).await {
+        None => return false,
+        Some(difficulty) => difficulty,
+    };
+    if difficulty > 0 {
+        println!("Solving proof of work with difficulty {difficulty}");
+        if let Err(why) = reid.solve_pow(difficulty, prv_key) {
+            panic!("Signing Reid failed: {why}");
+        }
+    }
+    save_published_seq(config_path, reid.get_seq());
     let endpoint: String = format!("http://{log_addr}/publish");
     let res = client
         .post(endpoint)
@@ -459,12 +845,414 @@ async fn publish_reid(log_addr: String, reid: Reid, pem_str: String) {
         .await
         .unwrap();
     match res.status() {
-        StatusCode::OK => println!("Successful append reid"),
+        StatusCode::OK => {
+            println!("Successful append reid");
+            return true;
+        }
         StatusCode::NOT_ACCEPTABLE => println!("Log server could not verify certificate"),
+        StatusCode::FORBIDDEN => println!("Log server rejected reid: id does not match public key"),
+        StatusCode::BAD_REQUEST => println!("Log server could not parse public key"),
+        StatusCode::PRECONDITION_REQUIRED => {
+            println!("Log server rejected reid: proof of work missing or too weak")
+        }
+        StatusCode::CONFLICT => println!(
+            "Log server rejected reid: seq is not newer than the last published record, update seq in graphlog.toml"
+        ),
+        code => println!("Unexpected status code: {code}"),
+    }
+    false
+}
+
+// Saves seq to the config before the reid carrying it is sent, so a monitor
+// following the log never sees a seq from this client the config doesn't
+// have yet. The saved seq only ever goes up.
+fn save_published_seq(config_path: &Path, seq: u64) {
+    let toml_str: String = match fs::read_to_string(config_path) {
+        Err(why) => {
+            println!("Error reading config: {why}");
+            return;
+        }
+        Ok(toml_str) => toml_str,
+    };
+    let mut config: Config = match toml::from_str(&toml_str) {
+        Err(why) => {
+            println!("Error loading toml: {why}");
+            return;
+        }
+        Ok(config) => config,
+    };
+    if let Some(client_config) = config.client_conf.as_mut() {
+        client_config.seq = client_config.seq.max(seq);
+        save_config(&config, config_path);
+    }
+}
+
+// Every bit doubles the work, past this a log could keep the client
+// hashing for days
+const MAX_POW_DIFFICULTY: u8 = 32;
+
+// None if the log can't be asked or wants more work than MAX_POW_DIFFICULTY
+async fn get_pow_difficulty(client: &Client, log_addr: &str) -> Option<u8> {
+    match client
+        .get(format!("http://{log_addr}/pow"))
+        .send()
+        .await
+        .unwrap()
+        .json::<PowMessage>()
+        .await
+    {
+        Err(why) => {
+            println!("Failed to get proof of work difficulty from log: {why}");
+            None
+        }
+        Ok(pow_msg) if pow_msg.difficulty > MAX_POW_DIFFICULTY => {
+            println!(
+                "Log asks for a proof of work of difficulty {}, more than the {MAX_POW_DIFFICULTY} bits this client will solve",
+                pow_msg.difficulty
+            );
+            None
+        }
+        Ok(pow_msg) => Some(pow_msg.difficulty),
+    }
+}
+
+// Endorse the claims of the reid currently published for id_b64, or only
+// the claims of claim_type if one is given
+async fn endorse_reid(
+    log_addr: String,
+    id_b64: String,
+    claim_type: Option<ClaimType>,
+    expiration: DateTime<Utc>,
+    pub_key: &PKey<Public>,
+    prv_key: &PKey<Private>,
+    log_key: &PKey<Public>,
+) {
+    let reid: Reid = match fetch_verified_reid(&log_addr, &id_b64, log_key).await {
+        None => return,
+        Some((_, reid)) => reid,
+    };
+    let mut endorsement = Endorsement::new_with_keys(pub_key, prv_key, expiration, None, None);
+    for (ct, key) in reid.get_claims().iter().flatten() {
+        if claim_type
+            .as_ref()
+            .is_none_or(|claim_type| claim_type == ct)
+        {
+            endorsement.append_endorsement(reid.get_id(), ct.clone(), key.clone());
+        }
+    }
+    if endorsement.get_endorsements().is_none() {
+        println!("Reid {id_b64} has no matching claims to endorse");
+        return;
+    }
+
+    let client = Client::new();
+    let difficulty: u8 = match get_pow_difficulty(&client, &log_addr).await {
+        None => return,
+        Some(difficulty) => difficulty,
+    };
+    let signed = if difficulty > 0 {
+        println!("Solving proof of work with difficulty {difficulty}");
+        endorsement.solve_pow(difficulty, prv_key)
+    } else {
+        endorsement.update_sig(prv_key)
+    };
+    if let Err(why) = signed {
+        panic!("Signing Endorsement failed: {why}");
+    }
+
+    let endpoint: String = format!("http://{log_addr}/endorse");
+    let res = client
+        .post(endpoint)
+        .json(&EndorsementMessage {
+            endorsement,
+            pub_key: pub_key_pem(pub_key),
+        })
+        .send()
+        .await
+        .unwrap();
+    match res.status() {
+        StatusCode::OK => println!("Successful append endorsement"),
+        StatusCode::NOT_ACCEPTABLE => println!("Log server could not verify endorsement"),
+        StatusCode::FORBIDDEN => {
+            println!("Log server rejected endorsement: id does not match public key")
+        }
+        StatusCode::BAD_REQUEST => println!("Log server could not parse public key"),
+        StatusCode::PRECONDITION_REQUIRED => {
+            println!("Log server rejected endorsement: proof of work missing or too weak")
+        }
+        StatusCode::UNPROCESSABLE_ENTITY => println!(
+            "Log server rejected endorsement: it has expired, or our reid or the endorsed claims are not live in the log"
+        ),
         code => println!("Unexpected status code: {code}"),
     }
 }
 
+// Matches are checked against the query and their own signatures so the
+// log can't pin an anchor on an id that never asserted it
+async fn search_reids(log_addr: String, search_msg: SearchMessage) {
+    let client = Client::new();
+    let endpoint: String = format!("http://{log_addr}/search");
+    let res = client
+        .post(endpoint)
+        .json(&search_msg)
+        .send()
+        .await
+        .unwrap();
+
+    let status: StatusCode = res.status();
+    if !status.is_success() {
+        println!("Search failed with status {status}");
+        return;
+    }
+    let reids: Vec<Reid> = match res.json::<Vec<Reid>>().await {
+        Err(why) => {
+            println!("Failed to parse json: {why}");
+            return;
+        }
+        Ok(reids) => reids,
+    };
+    let terms: Vec<String> = search_msg.terms();
+    let mut found: usize = 0;
+    for reid in reids {
+        let reid_terms: Vec<String> = reid.search_terms();
+        if !reid.verify() || !terms.iter().all(|term| reid_terms.contains(term)) {
+            println!(
+                "Log returned reid {} which doesn't match the search, skipping it",
+                encode_block(&reid.get_id())
+            );
+            continue;
+        }
+        found += 1;
+        println!("{}", encode_block(&reid.get_id()));
+    }
+    if found == 0 {
+        println!("No live reid matches the search");
+    }
+}
+
+async fn get_endorsers(log_addr: String, id_b64: String) {
+    let client = Client::new();
+    let endpoint: String = format!(
+        "http://{log_addr}/graph/endorsers/{}",
+        url_encode_id(&id_b64)
+    );
+    let res = client.get(endpoint).send().await.unwrap();
+
+    let status: StatusCode = res.status();
+    if status.is_success() {
+        match res.json::<Vec<TrustEdge>>().await {
+            Ok(edges) if edges.is_empty() => println!("No one endorses {id_b64}"),
+            Ok(edges) => {
+                for edge in edges {
+                    println!("{edge}");
+                }
+            }
+            Err(why) => println!("Failed to parse json: {why}"),
+        }
+    } else {
+        println!("Endorsers request failed with status {status}");
+    }
+}
+
+async fn get_trust_path(log_addr: String, from_b64: String, to_b64: String) {
+    let client = Client::new();
+    let endpoint: String = format!(
+        "http://{log_addr}/graph/path/{}/{}",
+        url_encode_id(&from_b64),
+        url_encode_id(&to_b64)
+    );
+    let res = client.get(endpoint).send().await.unwrap();
+
+    let status: StatusCode = res.status();
+    if status == StatusCode::NOT_FOUND {
+        println!("No chain of endorsements leads from {from_b64} to {to_b64}");
+    } else if status.is_success() {
+        match res.json::<Vec<TrustEdge>>().await {
+            Ok(path) => {
+                println!("Path of {} endorsement(s):", path.len());
+                for edge in path {
+                    println!("{edge}");
+                }
+            }
+            Err(why) => println!("Failed to parse json: {why}"),
+        }
+    } else {
+        println!("Trust path request failed with status {status}");
+    }
+}
+
+async fn get_reach(log_addr: String, roots: Vec<String>, hops: usize) {
+    let client = Client::new();
+    let endpoint: String = format!("http://{log_addr}/graph/reach");
+    let res = client
+        .post(endpoint)
+        .json(&ReachMessage { roots, hops })
+        .send()
+        .await
+        .unwrap();
+
+    let status: StatusCode = res.status();
+    if status.is_success() {
+        match res.json::<Vec<(Vec<u8>, usize)>>().await {
+            Ok(reached) => {
+                for (id, dist) in reached {
+                    println!("{dist}: {}", encode_block(&id));
+                }
+            }
+            Err(why) => println!("Failed to parse json: {why}"),
+        }
+    } else {
+        println!("Reach request failed with status {status}");
+    }
+}
+
+async fn export_graph(log_addr: String, format: GraphFormat, output: Option<PathBuf>) {
+    let client = Client::new();
+    let format_str: &str = match format {
+        GraphFormat::Dot => "dot",
+        GraphFormat::Graphml => "graphml",
+    };
+    let endpoint: String = format!("http://{log_addr}/graph/export/{format_str}");
+    let res = client.get(endpoint).send().await.unwrap();
+
+    let status: StatusCode = res.status();
+    if !status.is_success() {
+        println!("Graph export request failed with status {status}");
+        return;
+    }
+    let graph: String = match res.text().await {
+        Err(why) => {
+            println!("Failed to read graph export: {why}");
+            return;
+        }
+        Ok(graph) => graph,
+    };
+    match output {
+        None => print!("{graph}"),
+        Some(path) => match fs::write(&path, graph) {
+            Err(why) => println!("Couldn't write graph to {}: {why}", path.display()),
+            Ok(_) => println!("Wrote graph to {}", path.display()),
+        },
+    }
+}
+
+// Policies are evaluated locally against the endorsements the log hands
+// back, endorsements that don't verify never make it into the graph
+async fn evaluate_policy(
+    log_addr: String,
+    id_b64: String,
+    trust_policy: &TrustPolicy,
+    at: DateTime<Utc>,
+) -> Option<Verdict> {
+    let target: Vec<u8> = match decode_block(&id_b64) {
+        Err(why) => {
+            println!("Could not decode id {id_b64}: {why}");
+            return None;
+        }
+        Ok(target) => target,
+    };
+    let (endorsements, revocations) = fetch_endorsement_records(&log_addr).await?;
+    let graph = TrustGraph::from_records(&endorsements, &revocations);
+    Some(trust_policy.evaluate(&graph, &target, at))
+}
+
+async fn fetch_endorsement_records(
+    log_addr: &str,
+) -> Option<(Vec<Endorsement>, Vec<EndorsementRevocation>)> {
+    let endorsements: Vec<Endorsement> =
+        fetch_json(&format!("http://{log_addr}/endorsements")).await?;
+    let revocations: Vec<EndorsementRevocation> =
+        fetch_json(&format!("http://{log_addr}/revocations")).await?;
+    Some((endorsements, revocations))
+}
+
+async fn fetch_json<T>(endpoint: &str) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    let client = Client::new();
+    let res = client.get(endpoint).send().await.unwrap();
+
+    let status: StatusCode = res.status();
+    if !status.is_success() {
+        println!("Request to {endpoint} failed with status {status}");
+        return None;
+    }
+    match res.json::<T>().await {
+        Err(why) => {
+            println!("Failed to parse json: {why}");
+            None
+        }
+        Ok(val) => Some(val),
+    }
+}
+
+// Revokes every endorsement of id_b64 we made that isn't already revoked
+async fn revoke_endorsements(
+    log_addr: String,
+    id_b64: String,
+    pub_key: &PKey<Public>,
+    prv_key: &PKey<Private>,
+) {
+    let target: Vec<u8> = match decode_block(&id_b64) {
+        Err(why) => {
+            println!("Could not decode id {id_b64}: {why}");
+            return;
+        }
+        Ok(target) => target,
+    };
+    let (endorsements, revocations) = match fetch_endorsement_records(&log_addr).await {
+        None => return,
+        Some(records) => records,
+    };
+    let our_id: Vec<u8> = Reid::id_from_raw_key(&pub_key.raw_public_key().unwrap());
+    let to_revoke: Vec<&Endorsement> = endorsements
+        .iter()
+        .filter(|endorsement| endorsement.get_endorsing_id() == our_id)
+        .filter(|endorsement| {
+            endorsement
+                .get_endorsements()
+                .iter()
+                .flatten()
+                .any(|(id, _)| id == &target)
+        })
+        .filter(|endorsement| !revocations.iter().any(|r| r.revokes(endorsement)))
+        .collect();
+    if to_revoke.is_empty() {
+        println!("We have no live endorsements of {id_b64}");
+        return;
+    }
+
+    let client = Client::new();
+    let endpoint: String = format!("http://{log_addr}/endorse/revoke");
+    for endorsement in to_revoke {
+        let revocation =
+            EndorsementRevocation::new_with_keys(pub_key, prv_key, endorsement, Utc::now());
+        let res = client
+            .post(&endpoint)
+            .json(&RevocationMessage {
+                revocation,
+                pub_key: pub_key_pem(pub_key),
+            })
+            .send()
+            .await
+            .unwrap();
+        match res.status() {
+            StatusCode::OK => println!("Revoked endorsement of {id_b64}"),
+            StatusCode::NOT_ACCEPTABLE => println!("Log server could not verify revocation"),
+            StatusCode::FORBIDDEN => {
+                println!("Log server rejected revocation: id does not match public key")
+            }
+            StatusCode::BAD_REQUEST => println!("Log server could not parse public key"),
+            StatusCode::CONFLICT => println!("Endorsement was already revoked"),
+            StatusCode::UNPROCESSABLE_ENTITY => {
+                println!("Log server rejected revocation: no such endorsement or our clock is off")
+            }
+            code => println!("Unexpected status code: {code}"),
+        }
+    }
+}
+
 async fn get_tail(log_addr: String) {
     let client = Client::new();
     let endpoint: String = format!("http://{log_addr}/tail");
@@ -485,18 +1273,560 @@ async fn get_tail(log_addr: String) {
     }
 }
 
-async fn look_up_reid(log_addr: String, id_b64: String) {
+// When paging through the whole log from the start the entries are hashed
+// into a merkle tree to check they are the log the final tree head commits to
+async fn get_range(
+    log_addr: String,
+    start: u64,
+    limit: Option<usize>,
+    all: bool,
+    log_key: &PKey<Public>,
+) {
+    let client = Client::new();
+    let mut start: u64 = start;
+    let mut leaves: Vec<Hash> = Vec::new();
+    loop {
+        let mut endpoint: String = format!("http://{log_addr}/range?start={start}");
+        if let Some(limit) = limit {
+            endpoint.push_str(&format!("&limit={limit}"));
+        }
+        let res = client.get(endpoint).send().await.unwrap();
+        let status: StatusCode = res.status();
+        if !status.is_success() {
+            println!("Range request failed with status {status}");
+            return;
+        }
+        let page: RangeMessage = match res.json::<RangeMessage>().await {
+            Err(why) => {
+                println!("Failed to parse json: {why}");
+                return;
+            }
+            Ok(page) => page,
+        };
+        if !page.tree_head.verify_sig(log_key) {
+            println!("Range tree head is not signed by the log key, log may be misbehaving");
+            return;
+        }
+        for (index, record) in &page.entries {
+            leaves.push(record_leaf_hash(record));
+            match Reid::decode(record) {
+                None => println!("index {index}: reid doesn't decode"),
+                Some(reid) => println!("index {index}:\n{reid}"),
+            }
+        }
+        match page.next {
+            Some(next) if all && next > start => start = next,
+            Some(_) if all => {
+                println!("Range page didn't move past index {start}, log may be misbehaving");
+                return;
+            }
+            Some(next) => {
+                println!("More entries from index {next}, use --start {next} or --all");
+                return;
+            }
+            None => {
+                if all && leaves.len() as u64 == page.tree_head.tree_size {
+                    if MerkleTree::from_leaf_hashes(leaves).root() == page.tree_head.root_hash {
+                        println!(
+                            "Entries match the tree head of size {}",
+                            page.tree_head.tree_size
+                        );
+                    } else {
+                        println!("Entries don't match the tree head, log may be misbehaving");
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn watch_log(log_addr: String, start: Option<u64>, log_key: &PKey<Public>) {
+    follow_log(&log_addr, start, log_key, |index, reid| {
+        if reid.verify() {
+            println!("index {index}:\n{reid}");
+        } else {
+            println!(
+                "index {index}: reid {} is not signed by the key its id was derived from",
+                encode_block(&reid.get_id())
+            );
+        }
+    })
+    .await;
+}
+
+// Reads the server sent events from /watch and hands each reid to on_entry,
+// reconnecting after the last index seen if the connection drops. An event
+// only says which index was appended, the reid handed on is the one the log
+// proves is at that index under a tree head signed by log_key.
+async fn follow_log<F>(log_addr: &str, start: Option<u64>, log_key: &PKey<Public>, mut on_entry: F)
+where
+    F: FnMut(u64, Reid),
+{
+    let client = Client::new();
+    let mut next: Option<u64> = start;
+    loop {
+        let mut endpoint: String = format!("http://{log_addr}/watch");
+        if let Some(next) = next {
+            endpoint.push_str(&format!("?start={next}"));
+        }
+        match client.get(endpoint).send().await {
+            Err(why) => println!("Couldn't connect to {log_addr}: {why}"),
+            Ok(res) if !res.status().is_success() => {
+                println!("Watch request failed with status {}", res.status());
+                return;
+            }
+            Ok(mut res) => {
+                // Raw bytes since a chunk can end part way through a character
+                let mut buffer: Vec<u8> = Vec::new();
+                loop {
+                    let chunk = match res.chunk().await {
+                        Err(why) => {
+                            println!("Lost connection to {log_addr}: {why}");
+                            break;
+                        }
+                        Ok(None) => break,
+                        Ok(Some(chunk)) => chunk,
+                    };
+                    buffer.extend_from_slice(&chunk);
+                    while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                        let event: Vec<u8> = buffer.drain(..end + 2).collect();
+                        if let Some(index) = parse_watch_event(&event[..end]) {
+                            next = Some(index + 1);
+                            if let Some(reid) =
+                                logged_entry(&client, log_addr, index, log_key).await
+                            {
+                                on_entry(index, reid);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        println!("Reconnecting to {log_addr}");
+    }
+}
+
+// The entry at index, if the log can prove it is there under a tree head
+// signed by log_key
+async fn logged_entry(
+    client: &Client,
+    log_addr: &str,
+    index: u64,
+    log_key: &PKey<Public>,
+) -> Option<Reid> {
+    let endpoint: String = format!("http://{log_addr}/proof/index/{index}");
+    let proof: Option<InclusionProofMessage> = match client.get(endpoint).send().await {
+        Err(why) => {
+            println!("Couldn't fetch inclusion proof for index {index}: {why}");
+            None
+        }
+        Ok(res) => res.json::<InclusionProofMessage>().await.ok(),
+    };
+    let reid: Option<Reid> = proof
+        .filter(|proof| proof.index == index && proof.verify(log_key))
+        .and_then(|proof| proof.reid());
+    if reid.is_none() {
+        println!("index {index}: no inclusion proof under a tree head signed by the log key, log may be misbehaving");
+    }
+    reid
+}
+
+// Keep alive comments and anything unparsable are skipped
+fn parse_watch_event(event: &[u8]) -> Option<u64> {
+    let event: &str = match std::str::from_utf8(event) {
+        Err(why) => {
+            println!("Failed to parse event: {why}");
+            return None;
+        }
+        Ok(event) => event,
+    };
+    let data: String = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.trim_start())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    if data.is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<(u64, IgnoredAny)>(&data) {
+        Err(why) => {
+            println!("Failed to parse event: {why}");
+            None
+        }
+        Ok((index, _)) => Some(index),
+    }
+}
+
+// What the monitor has already checked, kept in monitor.toml next to
+// graphlog.toml so a restart picks up where it left off
+#[derive(Serialize, Deserialize, Default)]
+struct MonitorState {
+    next_index: u64,       // first log index not checked yet
+    last_seq: Option<u64>, // seq of the last reid for our id seen in the log
+}
+
+// Follows the log and checks every entry against our identity. Records for
+// our id have to be signed by our key and be ones this client published,
+// and no other id may claim one of our keys or assert one of our anchors.
+async fn monitor_identity(
+    log_addr: String,
+    start: Option<u64>,
+    pub_key: &PKey<Public>,
+    config_path: &Path,
+    log_key: &PKey<Public>,
+) {
+    let state_path: PathBuf = config_path.with_file_name("monitor.toml");
+    let mut state: MonitorState = match fs::read_to_string(&state_path) {
+        Err(_) => MonitorState::default(),
+        Ok(state_str) => match toml::from_str(&state_str) {
+            Err(why) => panic!("Error loading {}: {why}", state_path.display()),
+            Ok(state) => state,
+        },
+    };
+    if let Some(start) = start {
+        // Records before where the last run stopped are seen again
+        state.next_index = start;
+        state.last_seq = None;
+    }
+    let my_id: Vec<u8> = Reid::id_from_raw_key(&pub_key.raw_public_key().unwrap());
+    let my_id_b64: String = encode_block(&my_id);
+    println!(
+        "Monitoring {my_id_b64} from index {}, last known seq {}",
+        state.next_index,
+        state
+            .last_seq
+            .map_or(String::from("none"), |seq| seq.to_string())
+    );
+
+    follow_log(&log_addr, Some(state.next_index), log_key, |index, reid| {
+        // Reloaded for every entry since publishing from this client while
+        // the monitor runs changes what we expect to see
+        let client_config: ClientConfig = match load_client_config(config_path) {
+            None => return,
+            Some(client_config) => client_config,
+        };
+        if id_equal(reid.get_id(), my_id.clone()) {
+            match own_reid_alert(&reid, pub_key, client_config.seq, state.last_seq) {
+                Some(alert) => println!("ALERT index {index}: {alert}"),
+                None => println!(
+                    "index {index}: our reid seq {} is as expected",
+                    reid.get_seq()
+                ),
+            }
+            state.last_seq = state.last_seq.max(Some(reid.get_seq()));
+        } else if !reid.is_revoked() {
+            let id_b64: String = encode_block(&reid.get_id());
+            let reid_terms: Vec<String> = reid.search_terms();
+            for (claim_type, key) in client_config.claims.iter().flatten() {
+                if reid_terms.contains(&Reid::claim_term(&key.1)) {
+                    println!("ALERT index {index}: {id_b64} claims our {claim_type:?} key");
+                }
+            }
+            for (anchor_type, value) in client_config.anchors.iter().flatten() {
+                if reid_terms.contains(&Reid::anchor_term(anchor_type, value)) {
+                    println!(
+                        "ALERT index {index}: {id_b64} asserts our anchor {anchor_type:?} {value}"
+                    );
+                }
+            }
+        }
+        state.next_index = index + 1;
+        if let Err(why) = fs::write(&state_path, toml::to_string_pretty(&state).unwrap()) {
+            println!("Error saving monitor state: {why}");
+        }
+    })
+    .await;
+}
+
+// Why a reid for our id shouldn't be in the log, None if it is one we
+// published. published_seq is the last seq this client sent to the log and
+// last_seq the newest one already seen there for our id.
+fn own_reid_alert(
+    reid: &Reid,
+    pub_key: &PKey<Public>,
+    published_seq: u64,
+    last_seq: Option<u64>,
+) -> Option<String> {
+    if !reid.verify_binding(pub_key) || !reid.verify_sig(pub_key) {
+        Some(String::from("reid for our id is not signed by our key"))
+    } else if let Some(last_seq) = last_seq.filter(|last_seq| reid.get_seq() <= *last_seq) {
+        Some(format!(
+            "reid seq {} is not newer than seq {last_seq} earlier in the log, the log accepted a replay",
+            reid.get_seq()
+        ))
+    } else if reid.get_seq() > published_seq {
+        Some(format!(
+            "reid seq {} is signed by our key but was not published by this client, the key may be compromised",
+            reid.get_seq()
+        ))
+    } else {
+        None
+    }
+}
+
+fn load_client_config(config_path: &Path) -> Option<ClientConfig> {
+    let toml_str: String = match fs::read_to_string(config_path) {
+        Err(why) => {
+            println!("Error reading config: {why}");
+            return None;
+        }
+        Ok(toml_str) => toml_str,
+    };
+    match toml::from_str::<Config>(&toml_str) {
+        Err(why) => {
+            println!("Error loading toml: {why}");
+            None
+        }
+        Ok(config) => config.client_conf,
+    }
+}
+
+// The key tree heads have to be signed with. Nothing the log sends is
+// trusted until a key has been pinned with the log-key command.
+fn pinned_log_key(client_config: &ClientConfig) -> Option<PKey<Public>> {
+    let pinned: &str = match &client_config.log_key {
+        None => {
+            println!("No log key pinned, run the log-key command to pin the log's key first");
+            return None;
+        }
+        Some(pinned) => pinned,
+    };
+    match decode_block(pinned)
+        .ok()
+        .and_then(|raw| PKey::public_key_from_raw_bytes(&raw, Id::ED25519).ok())
+    {
+        None => {
+            println!("Pinned log key in config is not a valid Ed25519 key");
+            None
+        }
+        Some(log_key) => Some(log_key),
+    }
+}
+
+// The key the log signs tree heads with now, as long as it is the pinned key
+// or the log rotated to it from the pinned key
+async fn trusted_log_key(log_addr: &str, client_config: &ClientConfig) -> Option<PKey<Public>> {
+    let pinned: Vec<u8> = pinned_log_key(client_config)?.raw_public_key().unwrap();
+    let log_key_msg: LogKeyMessage = fetch_log_key(log_addr).await?;
+    if log_key_msg.pub_key == pinned {
+        log_key_msg.public_key()
+    } else if log_key_msg.has_used(&pinned) {
+        println!(
+            "Log key rotated from the pinned key {} to {}, run log-key to move the pin",
+            key_id(&pinned),
+            log_key_msg.key_id
+        );
+        log_key_msg.public_key()
+    } else {
+        println!(
+            "ALERT log key {} replaced the pinned key {} without a rotation signed by it",
+            log_key_msg.key_id,
+            key_id(&pinned)
+        );
+        None
+    }
+}
+
+// The log's key along with its rotations, None unless every rotation is
+// signed by the key before it
+async fn fetch_log_key(log_addr: &str) -> Option<LogKeyMessage> {
+    let log_key_msg: LogKeyMessage =
+        fetch_json(&format!("http://{log_addr}/.well-known/graphlog-log-key")).await?;
+    if !log_key_msg.verify() {
+        println!("Log key rotations don't verify, log may be misbehaving");
+        return None;
+    }
+    Some(log_key_msg)
+}
+
+// Fetches the log key and checks the current tree head is signed by it.
+// Returns the base64 key to pin if it is the pinned key, or the log rotated
+// to it from the pinned key
+async fn check_log_key(log_addr: &str, pinned: Option<&str>) -> Option<String> {
+    let log_key_msg: LogKeyMessage = fetch_log_key(log_addr).await?;
+    let tree_head: SignedTreeHead = fetch_json(&format!("http://{log_addr}/sth")).await?;
+    if !log_key_msg
+        .public_key()
+        .is_some_and(|log_key| tree_head.verify_sig(&log_key))
+    {
+        println!("Tree head is not signed by the log key, log may be misbehaving");
+        return None;
+    }
+
+    let current_id: String = key_id(&log_key_msg.pub_key);
+    match pinned.map(decode_block) {
+        None => println!("Pinned log key {current_id}"),
+        Some(Err(why)) => {
+            println!("Pinned log key in config is not valid base64: {why}");
+            return None;
+        }
+        Some(Ok(pinned)) if pinned == log_key_msg.pub_key => {
+            println!("Log key {current_id} matches the pinned key");
+        }
+        Some(Ok(pinned)) if log_key_msg.has_used(&pinned) => {
+            println!(
+                "Log key rotated from {} to {current_id}, following it",
+                key_id(&pinned)
+            );
+        }
+        Some(Ok(pinned)) => {
+            println!(
+                "ALERT log key {current_id} replaced the pinned key {} without a rotation signed by it",
+                key_id(&pinned)
+            );
+            return None;
+        }
+    }
+    println!(
+        "Tree head of size {} verifies under it, {} earlier rotations",
+        tree_head.tree_size,
+        log_key_msg.rotations.len()
+    );
+    Some(encode_block(&log_key_msg.pub_key))
+}
+
+async fn look_up_reid(log_addr: String, id_b64: String, log_key: &PKey<Public>) {
+    if let Some((proof, reid)) = fetch_verified_reid(&log_addr, &id_b64, log_key).await {
+        println!(
+            "Verified inclusion at index {} under tree head of size {}",
+            proof.index, proof.tree_head.tree_size
+        );
+        println!("Received reid: {reid}");
+    }
+}
+
+async fn get_history(log_addr: String, id_b64: String, log_key: &PKey<Public>) {
     let client = Client::new();
-    let endpoint: String = format!("http://{log_addr}/{id_b64}");
+    let endpoint: String = format!("http://{log_addr}/history/{}", url_encode_id(&id_b64));
     let res = client.get(endpoint).send().await.unwrap();
 
     let status: StatusCode = res.status();
+    if status == StatusCode::NO_CONTENT {
+        println!("Log has no reid with id {id_b64}");
+        return;
+    } else if !status.is_success() {
+        println!("History request for {id_b64} failed with status {status}");
+        return;
+    }
+    let history: HistoryMessage = match res.json::<HistoryMessage>().await {
+        Err(why) => {
+            println!("Failed to parse json: {why}");
+            return;
+        }
+        Ok(history) => history,
+    };
+    if !history.tree_head.verify_sig(log_key) {
+        println!("History tree head is not signed by the log key, log may be misbehaving");
+        return;
+    }
 
-    if status.is_success() {
-        match res.json::<Reid>().await {
-            Ok(reid) => {
-                println!("Received reid: {reid}");
+    let mut prev: Option<Reid> = None;
+    for (i, version) in history.versions.iter().enumerate() {
+        let reid: Reid = match version.reid() {
+            None => {
+                println!("Log returned a reid that doesn't decode, log may be misbehaving");
+                return;
+            }
+            Some(reid) => reid,
+        };
+        if encode_block(&reid.get_id()) != id_b64 {
+            println!("Log returned a reid for a different id, refusing to display it");
+            return;
+        }
+        let checked: &str = if !reid.verify() {
+            "bad signature"
+        } else if version.verify(&history.tree_head) {
+            "verified"
+        } else if version.audit_path.is_some() {
+            "inclusion proof failed, log may be misbehaving"
+        } else {
+            "no inclusion proof"
+        };
+        println!("version {} at index {} ({checked}):", i + 1, version.index);
+        print_reid_diff(prev.as_ref(), &reid);
+        prev = Some(reid);
+    }
+    println!(
+        "{} version(s) under tree head of size {}",
+        history.versions.len(),
+        history.tree_head.tree_size
+    );
+}
+
+// Prints what changed from prev to next, everything in next counts as added
+// for the first version
+fn print_reid_diff(prev: Option<&Reid>, next: &Reid) {
+    fn lines(reid: &Reid) -> Vec<String> {
+        let claims = reid.get_claims().iter().flatten().map(|(claim_type, key)| {
+            format!("claim {claim_type:?}: {}", Reid::key_to_pem(key).trim())
+        });
+        let anchors = reid
+            .get_anchors()
+            .iter()
+            .flatten()
+            .map(|(anchor_type, value)| format!("anchor {anchor_type:?}: {value}"));
+        claims.chain(anchors).collect()
+    }
+
+    let next_lines: Vec<String> = lines(next);
+    match prev {
+        None => println!("  seq {}", next.get_seq()),
+        Some(prev) => {
+            println!("  seq {} -> {}", prev.get_seq(), next.get_seq());
+            if prev.get_expiration() != next.get_expiration() {
+                println!(
+                    "  expiration {} -> {}",
+                    prev.get_expiration().to_rfc3339(),
+                    next.get_expiration().to_rfc3339()
+                );
+            }
+            for line in lines(prev).iter().filter(|line| !next_lines.contains(line)) {
+                println!("  - {line}");
             }
+        }
+    }
+    let prev_lines: Vec<String> = prev.map(lines).unwrap_or_default();
+    for line in next_lines.iter().filter(|line| !prev_lines.contains(line)) {
+        println!("  + {line}");
+    }
+    if next.is_revoked() && !prev.is_some_and(|prev| prev.is_revoked()) {
+        println!("  revoked");
+    }
+}
+
+// Fetch the latest reid for id_b64 and check its inclusion proof and
+// signature, printing why and returning None if anything doesn't verify
+async fn fetch_verified_reid(
+    log_addr: &str,
+    id_b64: &str,
+    log_key: &PKey<Public>,
+) -> Option<(InclusionProofMessage, Reid)> {
+    let client = Client::new();
+    let endpoint: String = format!("http://{log_addr}/proof/id/{}", url_encode_id(id_b64));
+    let res = client.get(endpoint).send().await.unwrap();
+
+    let status: StatusCode = res.status();
+
+    if status == StatusCode::NO_CONTENT {
+        println!("Log has no reid with id {id_b64}");
+    } else if status.is_success() {
+        match res.json::<InclusionProofMessage>().await {
+            Ok(proof) => match proof.reid() {
+                None => println!("Log returned a reid that doesn't decode"),
+                Some(reid) if encode_block(&reid.get_id()) != id_b64 => {
+                    println!("Log returned a reid for a different id, refusing to display it");
+                }
+                Some(_) if !proof.verify(log_key) => {
+                    println!("Inclusion proof for reid {id_b64} failed to verify against the log key, log may be misbehaving");
+                }
+                Some(reid) if !reid.verify() => {
+                    println!("Reid {id_b64} is not signed by the key its id was derived from");
+                }
+                Some(reid) => return Some((proof, reid)),
+            },
             Err(why) => {
                 println!("Failed to parse json: {why}");
             }
@@ -504,4 +1834,57 @@ async fn look_up_reid(log_addr: String, id_b64: String) {
     } else {
         println!("Look up of reid {id_b64} failed: {status}");
     }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphlog_proto::utils::keys::generate_ed25519;
+
+    fn own_reid(keys: &(PKey<Public>, PKey<Private>), seq: u64) -> Reid {
+        Reid::new_with_keys(
+            &keys.0,
+            &keys.1,
+            Utc::now() + chrono::Duration::days(1),
+            None,
+            None,
+            None,
+            false,
+            seq,
+        )
+    }
+
+    #[test]
+    fn published_reids_pass() {
+        let keys = generate_ed25519();
+        assert_eq!(own_reid_alert(&own_reid(&keys, 1), &keys.0, 1, None), None);
+        assert_eq!(
+            own_reid_alert(&own_reid(&keys, 3), &keys.0, 5, Some(2)),
+            None
+        );
+    }
+
+    #[test]
+    fn seq_this_client_never_sent_is_an_alert() {
+        let keys = generate_ed25519();
+        let alert = own_reid_alert(&own_reid(&keys, 6), &keys.0, 5, Some(5)).unwrap();
+        assert!(alert.contains("not published by this client"), "{alert}");
+    }
+
+    #[test]
+    fn seq_going_backwards_is_an_alert() {
+        let keys = generate_ed25519();
+        for seq in [4, 5] {
+            let alert = own_reid_alert(&own_reid(&keys, seq), &keys.0, 5, Some(5)).unwrap();
+            assert!(alert.contains("replay"), "{alert}");
+        }
+    }
+
+    #[test]
+    fn reid_signed_by_another_key_is_an_alert() {
+        let (keys, other) = (generate_ed25519(), generate_ed25519());
+        let alert = own_reid_alert(&own_reid(&other, 1), &keys.0, 1, None).unwrap();
+        assert!(alert.contains("not signed by our key"), "{alert}");
+    }
 }
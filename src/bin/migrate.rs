@@ -0,0 +1,118 @@
+// Converts a text format log (one base64 entry per line) into a directory of
+// binary segments. Entries are copied byte for byte so the merkle root of
+// the migrated log is the same as before, run it once for each of the reid,
+// endorsement and revocation logs. A partial last line, left by a write
+// that never finished, is dropped the same way the server drops it.
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use graphlog_proto::types::{
+    merkle::MerkleTree,
+    segment::{SegmentLog, DEFAULT_SEGMENT_BYTES},
+};
+use openssl::base64::{decode_block, encode_block};
+
+// Entries written to the segments at a time
+const MIGRATE_BATCH: usize = 1024;
+
+#[derive(Parser)]
+#[command(name = "graphlog log migration", version = "1.0")]
+#[command(about = "convert a text format log into binary segments")]
+struct Cli {
+    /// text format log to read
+    input: PathBuf,
+    /// directory to write the segments to, must not already hold a log
+    output: PathBuf,
+    #[arg(long, default_value_t = DEFAULT_SEGMENT_BYTES)]
+    segment_bytes: u64,
+}
+
+fn main() {
+    let cli: Cli = Cli::parse();
+    // Checked all the way through first so a bad line can't leave a half
+    // migrated log behind
+    read_entries(&cli.input, |_| {});
+    let mut segments: SegmentLog = match SegmentLog::open(&cli.output, cli.segment_bytes) {
+        Err(why) => panic!("Couldn't open {}: {why}", cli.output.display()),
+        Ok(segments) => segments,
+    };
+    if !segments.is_empty() {
+        panic!("{} already holds a log", cli.output.display());
+    }
+
+    let mut tree = MerkleTree::new();
+    let mut batch: Vec<String> = Vec::new();
+    let dropped: Option<usize> = read_entries(&cli.input, |entry_b64| {
+        tree.push(entry_b64.as_bytes());
+        batch.push(entry_b64.to_string());
+        if batch.len() == MIGRATE_BATCH {
+            write_batch(&mut segments, &mut batch);
+        }
+    });
+    write_batch(&mut segments, &mut batch);
+    if let Err(why) = segments.sync() {
+        panic!("Error syncing segments: {why}");
+    }
+    if let Some(bytes) = dropped {
+        println!(
+            "Dropped a partial last line of {bytes} bytes from {}",
+            cli.input.display()
+        );
+    }
+    println!(
+        "Migrated {} entries, merkle root {}",
+        segments.len(),
+        encode_block(&tree.root())
+    );
+}
+
+// Hands every whole line of the text log at path to visit and returns the
+// length of the partial last line if there is one. Panics on a line that
+// isn't base64 since the input is then not a text format log, or a damaged
+// one.
+fn read_entries<F>(path: &Path, mut visit: F) -> Option<usize>
+where
+    F: FnMut(&str),
+{
+    let input: File = match File::open(path) {
+        Err(why) => panic!("Couldn't open {}: {why}", path.display()),
+        Ok(input) => input,
+    };
+    let mut reader = BufReader::new(input);
+    let mut line: String = String::new();
+    for line_num in 1.. {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Err(why) => panic!("Error reading line {line_num}: {why}"),
+            Ok(0) => break,
+            Ok(_) => {}
+        }
+        let entry_b64: &str = match line.strip_suffix('\n') {
+            None => return Some(line.len()),
+            Some(entry_b64) => entry_b64,
+        };
+        if entry_b64.is_empty() || decode_block(entry_b64).is_err() {
+            panic!(
+                "Line {line_num} of {} is not a base64 entry",
+                path.display()
+            );
+        }
+        visit(entry_b64);
+    }
+    None
+}
+
+fn write_batch(segments: &mut SegmentLog, batch: &mut Vec<String>) {
+    if let Err(why) = segments.append(batch) {
+        panic!(
+            "Error writing entries {}..{}: {why}",
+            segments.len(),
+            segments.len() as usize + batch.len()
+        );
+    }
+    batch.clear();
+}
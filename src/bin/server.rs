@@ -1,95 +1,545 @@
+use std::fs;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use dialoguer::Input;
+use clap::Parser;
 
 use graphlog_proto::{
     types::{
-        common::{id_equal, Id},
+        common::{
+            default_server_addr, id_equal, Config, Decodable, Encodable, Id, Indexable,
+            ServerConfig,
+        },
+        endorsement::Endorsement,
+        graph::{GraphFormat, NodeLabels, TrustEdge, TrustGraph},
         log::Log,
+        log_key::{verify_rotations, LogKeyRotation},
+        merkle::{Hash, SignedTreeHead},
         reid::Reid,
+        revocation::EndorsementRevocation,
+        segment::DEFAULT_SEGMENT_BYTES,
+        storage::FsyncPolicy,
     },
-    utils::http_server::ReidMessage,
+    utils::group_commit::{
+        run_group_commit, GroupCommit, DEFAULT_COMMIT_BATCH, DEFAULT_COMMIT_DELAY_MS,
+    },
+    utils::http_server::{
+        ConsistencyProofMessage, EndorsementMessage, HistoryEntry, HistoryMessage,
+        InclusionProofMessage, LogKeyMessage, PowMessage, RangeMessage, ReachMessage, ReidMessage,
+        RevocationMessage, SearchMessage,
+    },
+    utils::keys::{generate_ed25519, key_id, load_private_key, raw_public_key, save_private_key},
 };
 
+use chrono::{Duration, Utc};
 use openssl::{
     base64::decode_block,
-    pkey::{PKey, Public},
+    pkey::{PKey, Private, Public},
 };
 
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
+    extract::{DefaultBodyLimit, Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
     routing::{get, post},
     serve, Router,
 };
+use futures_util::stream::{self, Stream};
 
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch};
+
+#[derive(Clone)]
+struct AppState {
+    log: Arc<Mutex<Log<Reid>>>,
+    endorsements: Arc<Mutex<Log<Endorsement>>>,
+    revocations: Arc<Mutex<Log<EndorsementRevocation>>>,
+    log_key: Arc<PKey<Private>>,
+    log_key_msg: Arc<LogKeyMessage>,
+    config: Arc<ServerConfig>,
+    graph: Arc<Mutex<GraphCache>>,
+}
+
+// Trust graph built from the first `endorsements` and `revocations` entries
+// of their logs, so each record's signature is only checked once
+#[derive(Default)]
+struct GraphCache {
+    graph: TrustGraph,
+    endorsements: usize,
+    revocations: usize,
+}
+
+// How far a revocation's timestamp may be from the server's clock
+const REVOCATION_SKEW_SECS: i64 = 300;
+
+#[derive(Deserialize)]
+struct ProofQuery {
+    tree_size: Option<usize>,
+}
+
+// Most entries a single range request returns unless configured otherwise
+const DEFAULT_RANGE_LIMIT: usize = 1000;
+
+// Same as axum's own default
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// Rotations of the log key, oldest first, kept in <log_key_path>.rotations
+#[derive(Serialize, Deserialize, Default)]
+struct LogKeyRotations {
+    rotations: Vec<LogKeyRotation>,
+}
+
+#[derive(Deserialize)]
+struct WatchQuery {
+    start: Option<usize>, // defaults to only entries appended from now on
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    start: Option<usize>, // defaults to the start of the log
+    limit: Option<usize>, // defaults to and is capped at max_range_limit
+}
+
+// Every setting can come from a flag, an environment variable or the
+// [server] table of the config file, in that order of precedence
+#[derive(Parser)]
+#[command(name = "graphlog server", version = "1.0")]
+#[command(about = "prototype graphlog transparency log server")]
+struct Cli {
+    /// TOML file with a [server] table
+    #[arg(short, long, env = "GRAPHLOG_CONFIG")]
+    config: Option<PathBuf>,
+    /// address and port to bind to [default: 127.0.0.1:7878]
+    #[arg(short, long, env = "GRAPHLOG_ADDR")]
+    addr: Option<String>,
+    /// path the logs are persisted at, leave out to keep them in memory
+    #[arg(short, long, env = "GRAPHLOG_PERSIST_PATH")]
+    persist_path: Option<String>,
+    /// proof of work difficulty in leading zero bits, 0 disables [default: 0]
+    #[arg(long, env = "GRAPHLOG_POW_DIFFICULTY")]
+    pow_difficulty: Option<u8>,
+    /// size in bytes at which log segments roll over [default: 64MiB]
+    #[arg(long, env = "GRAPHLOG_SEGMENT_BYTES")]
+    segment_bytes: Option<u64>,
+    /// when to fsync the logs: append, batch or periodic:<ms> [default: batch]
+    #[arg(long, env = "GRAPHLOG_FSYNC")]
+    fsync: Option<FsyncPolicy>,
+    /// most appends written together in one group commit
+    #[arg(long, env = "GRAPHLOG_COMMIT_BATCH")]
+    commit_batch: Option<usize>,
+    /// milliseconds an append may wait for a group commit
+    #[arg(long, env = "GRAPHLOG_COMMIT_DELAY_MS")]
+    commit_delay_ms: Option<u64>,
+    /// most entries a range or tail request returns
+    #[arg(long, env = "GRAPHLOG_MAX_RANGE_LIMIT")]
+    max_range_limit: Option<usize>,
+    /// largest request body accepted in bytes
+    #[arg(long, env = "GRAPHLOG_MAX_BODY_BYTES")]
+    max_body_bytes: Option<usize>,
+    /// PEM Ed25519 key to sign tree heads with, generated if missing
+    /// [default: <persist_path>.key]
+    #[arg(long, env = "GRAPHLOG_LOG_KEY_PATH")]
+    log_key_path: Option<String>,
+    /// replace the log key with a new one before starting, the old key signs
+    /// the hand over so clients can follow it
+    #[arg(long)]
+    rotate_log_key: bool,
+}
+
+impl Cli {
+    // Flags and environment variables override the config file
+    fn into_config(self) -> ServerConfig {
+        let file: ServerConfig = match &self.config {
+            None => ServerConfig {
+                addr: default_server_addr(),
+                ..ServerConfig::default()
+            },
+            Some(path) => load_server_config(path),
+        };
+        ServerConfig {
+            addr: self.addr.unwrap_or(file.addr),
+            persist_path: self.persist_path.or(file.persist_path),
+            pow_difficulty: self.pow_difficulty.or(file.pow_difficulty),
+            segment_bytes: self.segment_bytes.or(file.segment_bytes),
+            fsync: self.fsync.or(file.fsync),
+            commit_batch: self.commit_batch.or(file.commit_batch),
+            commit_delay_ms: self.commit_delay_ms.or(file.commit_delay_ms),
+            max_range_limit: self.max_range_limit.or(file.max_range_limit),
+            max_body_bytes: self.max_body_bytes.or(file.max_body_bytes),
+            log_key_path: self.log_key_path.or(file.log_key_path),
+        }
+    }
+}
+
+fn load_server_config(path: &PathBuf) -> ServerConfig {
+    let toml_str: String = match fs::read_to_string(path) {
+        Err(why) => panic!("Couldn't read config file {}: {why}", path.display()),
+        Ok(toml_str) => toml_str,
+    };
+    let config: Config = match toml::from_str(&toml_str) {
+        Err(why) => panic!("Error loading toml from {}: {why}", path.display()),
+        Ok(config) => config,
+    };
+    match config.server_conf {
+        None => panic!("Config file {} has no [server] table", path.display()),
+        Some(server_conf) => server_conf,
+    }
+}
+
+// Loads the log key at path, or generates it there on first start, along
+// with the rotations that led to it. With rotate a new key is generated and
+// staged at <path>.next, the rotation to it, signed by the current key, is
+// recorded and only then does the new key replace the old one. A rotation
+// interrupted part way is finished or dropped on the next start.
+fn open_log_key(path: &FsPath, rotate: bool) -> (PKey<Private>, Vec<LogKeyRotation>) {
+    let rotations_path: PathBuf = PathBuf::from(format!("{}.rotations", path.display()));
+    let next_path: PathBuf = PathBuf::from(format!("{}.next", path.display()));
+    let mut rotations: Vec<LogKeyRotation> = match fs::read_to_string(&rotations_path) {
+        Err(_) => Vec::new(),
+        Ok(toml_str) => match toml::from_str::<LogKeyRotations>(&toml_str) {
+            Err(why) => panic!("Error loading {}: {why}", rotations_path.display()),
+            Ok(log_key_rotations) => log_key_rotations.rotations,
+        },
+    };
+
+    if next_path.exists() {
+        let next_key: PKey<Private> = load_private_key(&next_path);
+        let next_id: String = key_id(&raw_public_key(&next_key));
+        if rotations
+            .last()
+            .is_some_and(|rotation| rotation.get_new_key() == raw_public_key(&next_key))
+        {
+            if let Err(why) = fs::rename(&next_path, path) {
+                panic!(
+                    "Couldn't move {} to {}: {why}",
+                    next_path.display(),
+                    path.display()
+                );
+            }
+            println!("Finished interrupted rotation to log key {next_id}");
+        } else {
+            if let Err(why) = fs::remove_file(&next_path) {
+                panic!("Couldn't remove {}: {why}", next_path.display());
+            }
+            println!("Dropped log key {next_id} from a rotation that was never recorded");
+        }
+    }
+
+    let mut log_key: PKey<Private> = if path.exists() {
+        load_private_key(path)
+    } else {
+        let (_, log_key) = generate_ed25519();
+        save_private_key(&log_key, path);
+        println!(
+            "Generated log key {} at {}",
+            key_id(&raw_public_key(&log_key)),
+            path.display()
+        );
+        log_key
+    };
+    if !verify_rotations(&rotations, &raw_public_key(&log_key)) {
+        panic!(
+            "Log key {} doesn't match the rotations in {}",
+            path.display(),
+            rotations_path.display()
+        );
+    }
+
+    if rotate {
+        let (new_pub_key, new_key) = generate_ed25519();
+        save_private_key(&new_key, &next_path);
+        let rotation: LogKeyRotation = match LogKeyRotation::new(&log_key, &new_pub_key, Utc::now())
+        {
+            Err(why) => panic!("Failed to sign log key rotation: {why}"),
+            Ok(rotation) => rotation,
+        };
+        rotations.push(rotation);
+        save_rotations(&rotations, &rotations_path);
+        if let Err(why) = fs::rename(&next_path, path) {
+            panic!(
+                "Couldn't move {} to {}: {why}",
+                next_path.display(),
+                path.display()
+            );
+        }
+        println!(
+            "Rotated log key from {} to {}",
+            key_id(&raw_public_key(&log_key)),
+            key_id(&raw_public_key(&new_key))
+        );
+        log_key = new_key;
+    }
+    (log_key, rotations)
+}
+
+// Written next to path and renamed over it, like the log key
+fn save_rotations(rotations: &[LogKeyRotation], path: &FsPath) {
+    let toml_str: String = toml::to_string_pretty(&LogKeyRotations {
+        rotations: rotations.to_vec(),
+    })
+    .unwrap();
+    let tmp_path: PathBuf = PathBuf::from(format!("{}.tmp", path.display()));
+    let written = fs::File::create(&tmp_path).and_then(|mut tmp_file| {
+        io::Write::write_all(&mut tmp_file, toml_str.as_bytes())?;
+        tmp_file.sync_all()
+    });
+    if let Err(why) = written.and_then(|_| fs::rename(&tmp_path, path)) {
+        panic!("Couldn't save {}: {why}", path.display());
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let addr_port: String = Input::new()
-        .with_prompt("Enter IP address to bind to")
-        .default(String::from("127.0.0.1:7878"))
-        .interact_text()
-        .unwrap();
-    let persist_file: Option<String> = Input::new()
-        .with_prompt("Enter the compelte file path to persist the log")
-        .allow_empty(true)
-        .interact_text()
-        .ok()
-        .filter(|s: &String| !s.trim().is_empty());
+    let cli: Cli = Cli::parse();
+    let rotate_log_key: bool = cli.rotate_log_key;
+    let config: ServerConfig = cli.into_config();
+    println!(
+        "Starting log on {} persisting to {}",
+        config.addr,
+        config.persist_path.as_deref().unwrap_or("memory only")
+    );
 
     // TODO make this cleaner, I know there is a much better way
     // organize this code, probably change log.rs too
-    let log: Arc<Mutex<Log<Reid>>>;
-    if let Some(path) = persist_file {
-        log = Arc::new(Mutex::new(Log::new_from_file(path)));
-    } else {
-        log = Arc::new(Mutex::new(Log::new(persist_file)));
+    let log: Arc<Mutex<Log<Reid>>> = open_log(&config, "");
+    let endorsements: Arc<Mutex<Log<Endorsement>>> = open_log(&config, ".endorsements");
+    let revocations: Arc<Mutex<Log<EndorsementRevocation>>> = open_log(&config, ".revocations");
+
+    // Appends are queued by the handlers and written in batches, a handler
+    // only answers once its batch is on disk
+    let group_commit = GroupCommit {
+        max_batch: config.commit_batch.unwrap_or(DEFAULT_COMMIT_BATCH),
+        max_delay: std::time::Duration::from_millis(
+            config.commit_delay_ms.unwrap_or(DEFAULT_COMMIT_DELAY_MS),
+        ),
+    };
+    tokio::spawn(run_group_commit(log.clone(), group_commit));
+    tokio::spawn(run_group_commit(endorsements.clone(), group_commit));
+    tokio::spawn(run_group_commit(revocations.clone(), group_commit));
+
+    if let Some(FsyncPolicy::Periodic { ms }) = config.fsync {
+        let writers = (
+            log.lock().unwrap().writer(),
+            endorsements.lock().unwrap().writer(),
+            revocations.lock().unwrap().writer(),
+        );
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_millis(ms));
+            loop {
+                interval.tick().await;
+                let writers = writers.clone();
+                // fsync off the runtime threads, like the group commits
+                let _ = tokio::task::spawn_blocking(move || {
+                    writers.0.sync();
+                    writers.1.sync();
+                    writers.2.sync();
+                })
+                .await;
+            }
+        });
     }
 
+    // A log that is persisted keeps its key next to it unless told otherwise,
+    // one that only lives in memory gets a new key every start
+    let log_key_path: Option<String> = config.log_key_path.clone().or(config
+        .persist_path
+        .as_ref()
+        .map(|path| format!("{path}.key")));
+    let (log_key, rotations): (PKey<Private>, Vec<LogKeyRotation>) = match &log_key_path {
+        None if rotate_log_key => panic!("Rotating the log key needs log_key_path or persist_path"),
+        None => (generate_ed25519().1, Vec::new()),
+        Some(path) => open_log_key(FsPath::new(path), rotate_log_key),
+    };
+    let log_key_raw: Vec<u8> = raw_public_key(&log_key);
+    println!("Signing tree heads with log key {}", key_id(&log_key_raw));
+    let log_key_msg = LogKeyMessage {
+        key_id: key_id(&log_key_raw),
+        pub_key: log_key_raw,
+        rotations,
+    };
+    let addr_port: String = config.addr.clone();
+    let state = AppState {
+        log,
+        endorsements,
+        revocations,
+        log_key: Arc::new(log_key),
+        log_key_msg: Arc::new(log_key_msg),
+        config: Arc::new(config),
+        graph: Arc::new(Mutex::new(GraphCache::default())),
+    };
+
     // Endpoints
     // /publish => post request, server receives a base64 encode reid
     // /tail => get request, server sends the reid at the end of the log
     // /tail_{num} => get request, retrieves most recent and num-1 reids before it
     //             => speical case for tail_all try to get all the log
-    //             => will need some way to quantify stailness to tell the client
+    //             => 400 if that is more than max_range_limit, use /range then
+    // /range => get request, ?start=n&limit=m page of reids with their indices
+    //        => next in the reply is the start of the following page
+    // /endorsements/range, /revocations/range => same for the other logs
+    // /watch => get request, server sent events stream of [index, reid] for every
+    //        => reid committed from ?start=n, or from now on. Each event's id is
+    //        => its index so a reconnect with Last-Event-ID picks up after it
+    // /sth => get request, server sends a signed tree head for the current log
+    // /.well-known/graphlog-log-key => get request, the key tree heads are
+    //             signed with and the signed rotations that led to it
+    // /proof/id/{id} => get request, most recent reid for id with an inclusion proof
+    // /proof/index/{index} => get request, reid at index with an inclusion proof
+    //             => both take ?tree_size=n to prove against an older tree head
+    // /history/{id} => get request, every reid published for id with inclusion proofs
+    // /search => post request, ids whose latest live reid asserts an anchor
+    //            and/or claims a key
+    // /consistency/{old_size} => get request, proof that the log at old_size
+    //             is a prefix of the current log
+    // /endorse => post request, server receives an endorsement of other ids' claims
+    // /pow => get request, proof of work difficulty required to publish
+    // /endorse/revoke => post request, server receives a revocation of one
+    //             of the sender's own endorsements
+    // /endorsements => get request, every endorsement in the log so clients
+    //             can evaluate their own trust policies
+    // /revocations => get request, every endorsement revocation in the log
+    // /graph/endorsers/{id} => get request, unexpired endorsements of id
+    // /graph/path/{from}/{to} => get request, shortest chain of endorsements
+    //             leading from one id to another
+    // /graph/reach => post request, every id within some number of hops of
+    //             a set of trust anchors
+    // /graph/export/{format} => get request, the endorsement graph as dot
+    //             or graphml for visualization
     // /{id} => get request, server attempts to look up reid at
+    let body_limit: DefaultBodyLimit = DefaultBodyLimit::max(
+        state
+            .config
+            .max_body_bytes
+            .unwrap_or(DEFAULT_MAX_BODY_BYTES),
+    );
     let app = Router::new()
         .route("/publish", post(publish))
-        .with_state(Arc::clone(&log))
         .route("/tail", get(tail))
-        .with_state(Arc::clone(&log))
         .route("/tail_{num}", get(tail_num))
-        .with_state(Arc::clone(&log))
+        .route("/range", get(range))
+        .route("/watch", get(watch_log))
+        .route("/endorsements/range", get(endorsements_range))
+        .route("/revocations/range", get(revocations_range))
+        .route("/sth", get(tree_head))
+        .route("/.well-known/graphlog-log-key", get(log_key_info))
+        .route("/proof/id/{id}", get(proof_by_id))
+        .route("/proof/index/{index}", get(proof_by_index))
+        .route("/consistency/{old_size}", get(consistency))
+        .route("/history/{id}", get(history))
+        .route("/search", post(search))
+        .route("/pow", get(pow_requirement))
+        .route("/endorse", post(endorse))
+        .route("/endorse/revoke", post(revoke_endorsement))
+        .route("/endorsements", get(all_endorsements))
+        .route("/revocations", get(all_revocations))
+        .route("/graph/endorsers/{id}", get(endorsers))
+        .route("/graph/path/{from}/{to}", get(trust_path))
+        .route("/graph/reach", post(reach))
+        .route("/graph/export/{format}", get(export_graph))
         .route("/{id}", get(lookup))
-        .with_state(Arc::clone(&log));
+        .layer(body_limit)
+        .with_state(state);
 
     let listener = TcpListener::bind(addr_port).await.unwrap();
     serve(listener, app).await.unwrap();
 }
 
-async fn publish(
-    State(log): State<Arc<Mutex<Log<Reid>>>>,
-    Json(reid_msg): Json<ReidMessage>,
-) -> StatusCode {
+// Each log is a directory of segments next to the persist path, text format
+// logs from before have to be converted with the migrate tool first
+fn open_log<T>(config: &ServerConfig, suffix: &str) -> Arc<Mutex<Log<T>>>
+where
+    T: Encodable + Indexable + Decodable<T> + Clone + Send + 'static,
+{
+    let mut log: Log<T> = match &config.persist_path {
+        None => Log::new(None),
+        Some(path) => {
+            let dir: String = format!("{path}{suffix}");
+            let log: Log<T> = Log::new_segmented(
+                dir.clone(),
+                config.segment_bytes.unwrap_or(DEFAULT_SEGMENT_BYTES),
+            );
+            println!("Opened {dir}: {}", log.recovery_report());
+            log
+        }
+    };
+    log.set_fsync_policy(config.fsync.unwrap_or_default());
+    Arc::new(Mutex::new(log))
+}
+
+async fn publish(State(state): State<AppState>, Json(reid_msg): Json<ReidMessage>) -> StatusCode {
     let reid: Reid = reid_msg.reid;
     let pubk_str: String = reid_msg.pub_key;
-    let pubk: PKey<Public> = PKey::public_key_from_pem(pubk_str.as_bytes()).unwrap();
-    if reid.verify_sig(&pubk) {
-        log.lock().unwrap().append(reid);
-        println!("Pushed reid to log");
-        StatusCode::OK
-    } else {
+    let pubk: PKey<Public> = match PKey::public_key_from_pem(pubk_str.as_bytes()) {
+        Err(why) => {
+            println!("Could not parse public key: {why}");
+            return StatusCode::BAD_REQUEST;
+        }
+        Ok(pubk) => pubk,
+    };
+    // Only reids logged before keys were embedded may leave the key out,
+    // anything published now has to carry the key it is published under
+    if reid.public_key().is_none() {
+        println!("Reid does not embed a valid public key");
+        StatusCode::BAD_REQUEST
+    } else if !reid.verify_binding(&pubk) {
+        println!("Reid id does not match the submitted public key");
+        StatusCode::FORBIDDEN
+    } else if !reid.verify_sig(&pubk) {
         println!("Could not verify signature");
         StatusCode::NOT_ACCEPTABLE
+    } else if !reid.verify_pow(state.config.pow_difficulty.unwrap_or(0)) {
+        println!("Reid is missing a valid proof of work");
+        StatusCode::PRECONDITION_REQUIRED
+    } else {
+        // Check and queue under the same lock so two publishes with the
+        // same seq can't both get in, queued reids count as well since they
+        // are about to be in the log
+        let committed = {
+            let mut log = state.log.lock().unwrap();
+            let queued_seq: Option<u64> = log
+                .queued()
+                .rev()
+                .find(|x: &&Reid| id_equal(x.get_id(), reid.get_id()))
+                .map(|latest| latest.get_seq());
+            let latest_seq: Option<u64> =
+                queued_seq.or_else(|| log.latest(&reid.get_id()).map(|latest| latest.get_seq()));
+            if latest_seq.is_some_and(|latest_seq| reid.get_seq() <= latest_seq) {
+                println!(
+                    "Rejected replayed or stale reid, seq {} is not newer than {}",
+                    reid.get_seq(),
+                    latest_seq.unwrap()
+                );
+                return StatusCode::CONFLICT;
+            }
+            log.queue(reid)
+        };
+        wait_for_commit(committed, "reid").await
     }
 }
 
-async fn tail(
-    State(log): State<Arc<Mutex<Log<Reid>>>>,
-) -> Result<Json<Reid>, (StatusCode, String)> {
-    match log.lock().unwrap().tail() {
+// Waits for the group commit holding a queued entry, OK means it is in the
+// log and on disk
+async fn wait_for_commit(committed: oneshot::Receiver<io::Result<()>>, what: &str) -> StatusCode {
+    match committed.await {
+        Err(why) => {
+            println!("Group commit stopped before writing {what}: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        Ok(Err(why)) => {
+            println!("Error appending {what} to log: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        Ok(Ok(())) => {
+            println!("Pushed {what} to log");
+            StatusCode::OK
+        }
+    }
+}
+
+async fn tail(State(state): State<AppState>) -> Result<Json<Reid>, (StatusCode, String)> {
+    match state.log.lock().unwrap().tail() {
         Some(entry) => Ok(Json(entry.clone())),
         None => Err((StatusCode::NO_CONTENT, "Empty log".to_string())),
     }
@@ -97,29 +547,162 @@ async fn tail(
 
 async fn tail_num(
     Path(num): Path<String>,
-    State(log): State<Arc<Mutex<Log<Reid>>>>,
+    State(state): State<AppState>,
 ) -> Result<Json<Vec<Reid>>, (StatusCode, String)> {
-    if num == "all" {
-        Err((StatusCode::NO_CONTENT, "all not implemented".to_string()))
+    let log = state.log.lock().unwrap();
+    let num: usize = if num == "all" {
+        log.len()
     } else {
-        Ok(Json(
-            log.lock()
-                .unwrap()
-                .tailn(num.parse().expect("Couldn't convert to usize")),
-        ))
+        match num.parse::<usize>() {
+            Err(why) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Expected a number or all, got {num}: {why}"),
+                ))
+            }
+            Ok(num) => num.min(log.len()),
+        }
+    };
+    // Limited like a range request, but refused rather than cut short so a
+    // caller never mistakes part of the log for all of it
+    let max_limit: usize = range_limit(&state.config);
+    if num > max_limit {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{num} entries is more than the {max_limit} a request returns, page through the log with /range"
+            ),
+        ));
     }
+    Ok(Json(log.tailn(num)))
+}
+
+async fn range(
+    Query(query): Query<RangeQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<RangeMessage>, (StatusCode, String)> {
+    range_of(&state.log.lock().unwrap(), query, &state)
+}
+
+async fn endorsements_range(
+    Query(query): Query<RangeQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<RangeMessage>, (StatusCode, String)> {
+    range_of(&state.endorsements.lock().unwrap(), query, &state)
+}
+
+async fn revocations_range(
+    Query(query): Query<RangeQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<RangeMessage>, (StatusCode, String)> {
+    range_of(&state.revocations.lock().unwrap(), query, &state)
+}
+
+async fn watch_log(
+    Query(query): Query<WatchQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, (StatusCode, String)> {
+    let last_event_id: Option<usize> = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<usize>().ok());
+    // The header comes from the client, no log is ever long enough for the
+    // entry after usize::MAX
+    let resume_at: Option<usize> = match last_event_id {
+        None => None,
+        Some(last) => match last.checked_add(1) {
+            None => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Last-Event-ID {last} is past the end of the log"),
+                ))
+            }
+            Some(next) => Some(next),
+        },
+    };
+    let (start, committed): (usize, watch::Receiver<usize>) = {
+        let log = state.log.lock().unwrap();
+        let start: usize = match (resume_at, query.start) {
+            (Some(next), _) => next,
+            (None, Some(start)) => start,
+            (None, None) => log.len(),
+        };
+        (start, log.subscribe())
+    };
+    let entries = stream::unfold(
+        (state, start, committed),
+        |(state, next, mut committed)| async move {
+            loop {
+                let entry: Option<Reid> = state.log.lock().unwrap().get(next).cloned();
+                if let Some(reid) = entry {
+                    let event = Event::default()
+                        .event("reid")
+                        .id(next.to_string())
+                        .json_data((next as u64, reid));
+                    return Some((event, (state, next + 1, committed)));
+                }
+                // The log only goes away when the server stops
+                if committed.changed().await.is_err() {
+                    return None;
+                }
+            }
+        },
+    );
+    Ok(Sse::new(entries).keep_alive(KeepAlive::default()))
+}
+
+// The page and the tree head come from the same lock so every entry in the
+// page is covered by the tree head
+fn range_of<T>(
+    log: &Log<T>,
+    query: RangeQuery,
+    state: &AppState,
+) -> Result<Json<RangeMessage>, (StatusCode, String)> {
+    let max_limit: usize = range_limit(&state.config);
+    let start: usize = query.start.unwrap_or(0);
+    // At least one entry so paging through the log always moves forward
+    let limit: usize = query.limit.unwrap_or(max_limit).clamp(1, max_limit);
+    if start > log.len() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Log has fewer than {start} entries"),
+        ));
+    }
+    let tree_head: SignedTreeHead = match log.tree_head(&state.log_key) {
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to sign tree head".to_string(),
+            ))
+        }
+        Ok(tree_head) => tree_head,
+    };
+    let entries: Vec<(u64, String)> = log
+        .record_range(start, limit)
+        .iter()
+        .enumerate()
+        .map(|(i, record)| ((start + i) as u64, record.clone()))
+        .collect();
+    let end: usize = start + entries.len();
+    Ok(Json(RangeMessage {
+        tree_head,
+        entries,
+        next: (end < log.len()).then_some(end as u64),
+    }))
+}
+
+// Most entries a range or tail request returns, never 0
+fn range_limit(config: &ServerConfig) -> usize {
+    config.max_range_limit.unwrap_or(DEFAULT_RANGE_LIMIT).max(1)
 }
 
 async fn lookup(
     Path(id_b64): Path<String>,
-    State(log): State<Arc<Mutex<Log<Reid>>>>,
+    State(state): State<AppState>,
 ) -> Result<Json<Reid>, (StatusCode, String)> {
-    let id: Id = decode_block(&id_b64).unwrap();
-    match log
-        .lock()
-        .unwrap()
-        .search(|x: &Reid| id_equal(x.get_id(), id.clone()))
-    {
+    let id: Id = decode_id(&id_b64)?;
+    match state.log.lock().unwrap().latest(&id) {
         None => {
             println!("Could not find reid with id");
             Err((
@@ -133,3 +716,708 @@ async fn lookup(
         }
     }
 }
+
+async fn tree_head(
+    State(state): State<AppState>,
+) -> Result<Json<SignedTreeHead>, (StatusCode, String)> {
+    match state.log.lock().unwrap().tree_head(&state.log_key) {
+        Ok(sth) => Ok(Json(sth)),
+        Err(why) => {
+            println!("Failed to sign tree head: {why}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to sign tree head".to_string(),
+            ))
+        }
+    }
+}
+
+async fn log_key_info(State(state): State<AppState>) -> Json<LogKeyMessage> {
+    Json(state.log_key_msg.as_ref().clone())
+}
+
+async fn proof_by_id(
+    Path(id_b64): Path<String>,
+    Query(query): Query<ProofQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<InclusionProofMessage>, (StatusCode, String)> {
+    let id: Id = match decode_block(&id_b64) {
+        Err(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Id is not valid base64: {id_b64}"),
+            ))
+        }
+        Ok(id) => id,
+    };
+    let log = state.log.lock().unwrap();
+    match log.latest_position(&id) {
+        None => Err((
+            StatusCode::NO_CONTENT,
+            format!("Failed to find Reid with id: {id_b64}"),
+        )),
+        Some(index) => inclusion_proof(&log, index, query.tree_size, &state.log_key),
+    }
+}
+
+async fn proof_by_index(
+    Path(index): Path<usize>,
+    Query(query): Query<ProofQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<InclusionProofMessage>, (StatusCode, String)> {
+    let log = state.log.lock().unwrap();
+    inclusion_proof(&log, index, query.tree_size, &state.log_key)
+}
+
+fn inclusion_proof(
+    log: &Log<Reid>,
+    index: usize,
+    tree_size: Option<usize>,
+    log_key: &PKey<Private>,
+) -> Result<Json<InclusionProofMessage>, (StatusCode, String)> {
+    let tree_size: usize = tree_size.unwrap_or(log.len());
+    let (record, audit_path) = match (log.record(index), log.inclusion_proof(index, tree_size)) {
+        (Some(record), Some(audit_path)) => (record.to_string(), audit_path),
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("No entry {index} in tree of size {tree_size}"),
+            ))
+        }
+    };
+    match log.tree_head_at(tree_size, log_key) {
+        Some(Ok(tree_head)) => Ok(Json(InclusionProofMessage {
+            index: index as u64,
+            record,
+            tree_head,
+            audit_path,
+        })),
+        _ => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to sign tree head".to_string(),
+        )),
+    }
+}
+
+async fn history(
+    Path(id_b64): Path<String>,
+    Query(query): Query<ProofQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<HistoryMessage>, (StatusCode, String)> {
+    let id: Id = decode_id(&id_b64)?;
+    let log = state.log.lock().unwrap();
+    if log.positions(&id).is_empty() {
+        return Err((
+            StatusCode::NO_CONTENT,
+            format!("Failed to find Reid with id: {id_b64}"),
+        ));
+    }
+    let tree_size: usize = query.tree_size.unwrap_or(log.len());
+    let tree_head: SignedTreeHead = match log.tree_head_at(tree_size, &state.log_key) {
+        None => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Log has fewer than {tree_size} entries"),
+            ))
+        }
+        Some(Err(_)) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to sign tree head".to_string(),
+            ))
+        }
+        Some(Ok(tree_head)) => tree_head,
+    };
+    let versions: Vec<HistoryEntry> = log
+        .history_records(&id)
+        .map(|(index, record)| HistoryEntry {
+            index: index as u64,
+            record: record.to_string(),
+            audit_path: log.inclusion_proof(index, tree_size),
+        })
+        .collect();
+    Ok(Json(HistoryMessage {
+        tree_head,
+        versions,
+    }))
+}
+
+// Only an id's latest reid counts, and only if it is neither revoked nor
+// expired, so an anchor that was dropped or moved to another id stops
+// matching the old one
+async fn search(
+    State(state): State<AppState>,
+    Json(search_msg): Json<SearchMessage>,
+) -> Result<Json<Vec<Reid>>, (StatusCode, String)> {
+    let terms: Vec<String> = search_msg.terms();
+    if terms.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Search needs an anchor or a claim key".to_string(),
+        ));
+    }
+    let now = Utc::now();
+    let log = state.log.lock().unwrap();
+    let matches: Vec<Reid> = log
+        .term_positions(&terms[0])
+        .iter()
+        .filter_map(|pos| log.get(*pos).map(|reid| (*pos, reid)))
+        .filter(|(pos, reid)| log.latest_position(&reid.get_id()) == Some(*pos))
+        .filter(|(_, reid)| !reid.is_revoked() && reid.get_expiration() > now)
+        .filter(|(_, reid)| {
+            let reid_terms: Vec<String> = reid.search_terms();
+            terms.iter().all(|term| reid_terms.contains(term))
+        })
+        .map(|(_, reid)| reid.clone())
+        .collect();
+    Ok(Json(matches))
+}
+
+async fn consistency(
+    Path(old_size): Path<usize>,
+    State(state): State<AppState>,
+) -> Result<Json<ConsistencyProofMessage>, (StatusCode, String)> {
+    let log = state.log.lock().unwrap();
+    let proof: Vec<Hash> = match log.consistency_proof(old_size) {
+        None => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Log has fewer than {old_size} entries"),
+            ))
+        }
+        Some(proof) => proof,
+    };
+    match log.tree_head(&state.log_key) {
+        Ok(tree_head) => Ok(Json(ConsistencyProofMessage {
+            old_size: old_size as u64,
+            tree_head,
+            proof,
+        })),
+        Err(why) => {
+            println!("Failed to sign tree head: {why}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to sign tree head".to_string(),
+            ))
+        }
+    }
+}
+
+async fn pow_requirement(State(state): State<AppState>) -> Json<PowMessage> {
+    Json(PowMessage {
+        difficulty: state.config.pow_difficulty.unwrap_or(0),
+    })
+}
+
+async fn endorse(
+    State(state): State<AppState>,
+    Json(endorsement_msg): Json<EndorsementMessage>,
+) -> StatusCode {
+    let endorsement: Endorsement = endorsement_msg.endorsement;
+    let pubk: PKey<Public> = match PKey::public_key_from_pem(endorsement_msg.pub_key.as_bytes()) {
+        Err(why) => {
+            println!("Could not parse public key: {why}");
+            return StatusCode::BAD_REQUEST;
+        }
+        Ok(pubk) => pubk,
+    };
+    if !endorsement.verify_binding(&pubk) {
+        println!("Endorsing id does not match the submitted public key");
+        return StatusCode::FORBIDDEN;
+    }
+    if !endorsement.verify_sig(&pubk) {
+        println!("Could not verify endorsement signature");
+        return StatusCode::NOT_ACCEPTABLE;
+    }
+    if !endorsement.verify_pow(state.config.pow_difficulty.unwrap_or(0)) {
+        println!("Endorsement is missing a valid proof of work");
+        return StatusCode::PRECONDITION_REQUIRED;
+    }
+    if endorsement.get_expiration() <= Utc::now() {
+        println!("Endorsement has already expired");
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+
+    // Both the endorser and every endorsed claim have to be live in the log
+    {
+        let log = state.log.lock().unwrap();
+        let endorsing_id: Id = endorsement.get_endorsing_id();
+        match log.latest(&endorsing_id) {
+            Some(reid) if !reid.is_revoked() => {}
+            _ => {
+                println!("Endorser has no live reid in the log");
+                return StatusCode::UNPROCESSABLE_ENTITY;
+            }
+        }
+        for (endorsed_id, claim) in endorsement.get_endorsements().iter().flatten() {
+            let asserted: bool = match log.latest(endorsed_id) {
+                Some(reid) if !reid.is_revoked() => {
+                    reid.get_claims().iter().flatten().any(|c| c == claim)
+                }
+                _ => false,
+            };
+            if !asserted {
+                println!("Endorsed claim is not asserted by a live reid");
+                return StatusCode::UNPROCESSABLE_ENTITY;
+            }
+        }
+    }
+
+    let committed = state.endorsements.lock().unwrap().queue(endorsement);
+    wait_for_commit(committed, "endorsement").await
+}
+
+async fn revoke_endorsement(
+    State(state): State<AppState>,
+    Json(revocation_msg): Json<RevocationMessage>,
+) -> StatusCode {
+    let revocation: EndorsementRevocation = revocation_msg.revocation;
+    let pubk: PKey<Public> = match PKey::public_key_from_pem(revocation_msg.pub_key.as_bytes()) {
+        Err(why) => {
+            println!("Could not parse public key: {why}");
+            return StatusCode::BAD_REQUEST;
+        }
+        Ok(pubk) => pubk,
+    };
+    if !revocation.verify_binding(&pubk) {
+        println!("Endorsing id does not match the submitted public key");
+        return StatusCode::FORBIDDEN;
+    }
+    if !revocation.verify_sig(&pubk) {
+        println!("Could not verify revocation signature");
+        return StatusCode::NOT_ACCEPTABLE;
+    }
+    // A revocation can't be backdated to rewrite how the graph looked in
+    // the past, or postdated to sit in the log doing nothing
+    if (revocation.get_revoked_at() - Utc::now()).abs() > Duration::seconds(REVOCATION_SKEW_SECS) {
+        println!("Revocation time is too far from the current time");
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+
+    let endorsing_id: Id = revocation.get_endorsing_id();
+    let committed = {
+        let endorsements = state.endorsements.lock().unwrap();
+        if !endorsements
+            .history(&endorsing_id)
+            .any(|(_, x)| revocation.revokes(x))
+        {
+            println!("Revocation does not match any endorsement by the sender");
+            return StatusCode::UNPROCESSABLE_ENTITY;
+        }
+        let mut revocations = state.revocations.lock().unwrap();
+        if revocations
+            .history(&endorsing_id)
+            .map(|(_, x)| x)
+            .chain(revocations.queued())
+            .any(|x| {
+                id_equal(x.get_endorsing_id(), endorsing_id.clone())
+                    && x.get_endorsement() == revocation.get_endorsement()
+            })
+        {
+            println!("Endorsement has already been revoked");
+            return StatusCode::CONFLICT;
+        }
+        revocations.queue(revocation)
+    };
+    wait_for_commit(committed, "endorsement revocation").await
+}
+
+async fn all_endorsements(State(state): State<AppState>) -> Json<Vec<Endorsement>> {
+    Json(state.endorsements.lock().unwrap().iter().cloned().collect())
+}
+
+async fn all_revocations(State(state): State<AppState>) -> Json<Vec<EndorsementRevocation>> {
+    Json(state.revocations.lock().unwrap().iter().cloned().collect())
+}
+
+// Catches the cached graph up with the endorsement and revocation logs and
+// returns the part of it that is live now. Only the records committed since
+// the last query are copied out under the log locks, and their signatures
+// are checked with no lock held. A revocation is only accepted for an
+// endorsement that is already committed, so adding the new endorsements
+// before the new revocations never misses an edge.
+fn trust_graph(state: &AppState) -> TrustGraph {
+    let (endorsements_from, revocations_from): (usize, usize) = {
+        let cache = state.graph.lock().unwrap();
+        (cache.endorsements, cache.revocations)
+    };
+    let (endorsements, revocations): (Vec<Endorsement>, Vec<EndorsementRevocation>) = {
+        let endorsements = state.endorsements.lock().unwrap();
+        let revocations = state.revocations.lock().unwrap();
+        (
+            endorsements.range(endorsements_from, usize::MAX).to_vec(),
+            revocations.range(revocations_from, usize::MAX).to_vec(),
+        )
+    };
+    let endorsements: Vec<(Endorsement, bool)> = endorsements
+        .into_iter()
+        .map(|endorsement| {
+            let valid: bool = endorsement.verify();
+            (endorsement, valid)
+        })
+        .collect();
+    let revocations: Vec<(EndorsementRevocation, bool)> = revocations
+        .into_iter()
+        .map(|revocation| {
+            let valid: bool = revocation.verify();
+            (revocation, valid)
+        })
+        .collect();
+
+    let mut cache = state.graph.lock().unwrap();
+    // Another query may have caught the cache up past some of these while
+    // they were being checked
+    let seen: usize = cache.endorsements - endorsements_from;
+    for (endorsement, _) in endorsements.iter().skip(seen).filter(|(_, valid)| *valid) {
+        cache.graph.add_verified_endorsement(endorsement);
+    }
+    let seen: usize = cache.revocations - revocations_from;
+    for (revocation, _) in revocations.iter().skip(seen).filter(|(_, valid)| *valid) {
+        cache.graph.add_verified_revocation(revocation);
+    }
+    cache.endorsements = cache
+        .endorsements
+        .max(endorsements_from + endorsements.len());
+    cache.revocations = cache.revocations.max(revocations_from + revocations.len());
+    cache.graph.live_at(Utc::now())
+}
+
+fn decode_id(id_b64: &str) -> Result<Id, (StatusCode, String)> {
+    decode_block(id_b64).map_err(|why| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Could not decode id {id_b64}: {why}"),
+        )
+    })
+}
+
+async fn endorsers(
+    Path(id_b64): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<TrustEdge>>, (StatusCode, String)> {
+    let id: Id = decode_id(&id_b64)?;
+    Ok(Json(trust_graph(&state).endorsers_of(&id).to_vec()))
+}
+
+async fn trust_path(
+    Path((from_b64, to_b64)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<TrustEdge>>, (StatusCode, String)> {
+    let from: Id = decode_id(&from_b64)?;
+    let to: Id = decode_id(&to_b64)?;
+    match trust_graph(&state).shortest_path(&from, &to) {
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("No endorsement path from {from_b64} to {to_b64}"),
+        )),
+        Some(path) => Ok(Json(path)),
+    }
+}
+
+async fn reach(
+    State(state): State<AppState>,
+    Json(reach_msg): Json<ReachMessage>,
+) -> Result<Json<Vec<(Id, usize)>>, (StatusCode, String)> {
+    let roots: Vec<Id> = reach_msg
+        .roots
+        .iter()
+        .map(|root| decode_id(root))
+        .collect::<Result<_, _>>()?;
+    Ok(Json(
+        trust_graph(&state).within_hops(&roots, reach_msg.hops),
+    ))
+}
+
+async fn export_graph(Path(format): Path<GraphFormat>, State(state): State<AppState>) -> String {
+    let graph: TrustGraph = trust_graph(&state);
+    let mut labels: NodeLabels = NodeLabels::new();
+    {
+        let log = state.log.lock().unwrap();
+        for id in graph.nodes() {
+            if let Some(reid) = log.latest(&id) {
+                labels.insert(id, reid.get_anchors().clone().unwrap_or_default());
+            }
+        }
+    }
+    graph.export(format, &labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+    use futures_util::StreamExt;
+    use graphlog_proto::types::common::{ClaimType, KeyType};
+
+    // State for a log that only lives in memory, with no group committer
+    // running so queued entries stay queued
+    fn test_state() -> AppState {
+        let log_key: PKey<Private> = generate_ed25519().1;
+        let log_key_raw: Vec<u8> = raw_public_key(&log_key);
+        AppState {
+            log: Arc::new(Mutex::new(Log::new(None))),
+            endorsements: Arc::new(Mutex::new(Log::new(None))),
+            revocations: Arc::new(Mutex::new(Log::new(None))),
+            log_key: Arc::new(log_key),
+            log_key_msg: Arc::new(LogKeyMessage {
+                key_id: key_id(&log_key_raw),
+                pub_key: log_key_raw,
+                rotations: Vec::new(),
+            }),
+            config: Arc::new(ServerConfig::default()),
+            graph: Arc::new(Mutex::new(GraphCache::default())),
+        }
+    }
+
+    fn signed_reid(keys: &(PKey<Public>, PKey<Private>), seq: u64) -> Reid {
+        Reid::new_with_keys(
+            &keys.0,
+            &keys.1,
+            Utc::now() + Duration::days(1),
+            None,
+            None,
+            None,
+            false,
+            seq,
+        )
+    }
+
+    fn last_event_id(id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("last-event-id", HeaderValue::from_str(id).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn watch_resumes_after_last_event_id() {
+        let state: AppState = test_state();
+        let keys = generate_ed25519();
+        for seq in 1..=3 {
+            state
+                .log
+                .lock()
+                .unwrap()
+                .append(signed_reid(&keys, seq))
+                .unwrap();
+        }
+        let sse = match watch_log(
+            Query(WatchQuery { start: None }),
+            last_event_id("0"),
+            State(state),
+        )
+        .await
+        {
+            Err((status, why)) => panic!("watch failed with {status}: {why}"),
+            Ok(sse) => sse,
+        };
+        let mut body = sse.into_response().into_body().into_data_stream();
+        let event: Vec<u8> = body.next().await.unwrap().unwrap().to_vec();
+        let event: &str = std::str::from_utf8(&event).unwrap();
+        assert!(event.contains("id: 1\n"), "{event}");
+    }
+
+    #[tokio::test]
+    async fn watch_rejects_last_event_id_at_the_end_of_usize() {
+        let headers: HeaderMap = last_event_id(&usize::MAX.to_string());
+        let result = watch_log(
+            Query(WatchQuery { start: None }),
+            headers,
+            State(test_state()),
+        )
+        .await;
+        match result {
+            Ok(_) => panic!("Last-Event-ID of usize::MAX was accepted"),
+            Err((status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+        }
+    }
+
+    #[tokio::test]
+    async fn tail_refuses_more_than_the_range_limit() {
+        let mut state: AppState = test_state();
+        state.config = Arc::new(ServerConfig {
+            max_range_limit: Some(2),
+            ..Default::default()
+        });
+        let keys = generate_ed25519();
+        for seq in 1..=2 {
+            state
+                .log
+                .lock()
+                .unwrap()
+                .append(signed_reid(&keys, seq))
+                .unwrap();
+        }
+        let tail = |num: &str| tail_num(Path(num.to_string()), State(state.clone()));
+        match tail("all").await {
+            Err((status, why)) => panic!("tail_all failed with {status}: {why}"),
+            Ok(Json(reids)) => assert_eq!(reids.len(), 2),
+        }
+        // Asking past the end of the log is fine while the log fits
+        match tail("5").await {
+            Err((status, why)) => panic!("tail_5 failed with {status}: {why}"),
+            Ok(Json(reids)) => assert_eq!(reids.len(), 2),
+        }
+
+        state
+            .log
+            .lock()
+            .unwrap()
+            .append(signed_reid(&keys, 3))
+            .unwrap();
+        for num in ["all", "3"] {
+            match tail(num).await {
+                Ok(_) => panic!("tail_{num} returned part of a log longer than the limit"),
+                Err((status, why)) => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert!(why.contains("/range"), "{why}");
+                }
+            }
+        }
+        match tail("2").await {
+            Err((status, why)) => panic!("tail_2 failed with {status}: {why}"),
+            Ok(Json(reids)) => assert_eq!(reids.len(), 2),
+        }
+    }
+
+    fn endorse(
+        keys: &(PKey<Public>, PKey<Private>),
+        endorsed: &(PKey<Public>, PKey<Private>),
+    ) -> Endorsement {
+        let endorsed_id: Id = Reid::id_from_raw_key(&endorsed.0.raw_public_key().unwrap());
+        let claim = (
+            ClaimType::SSHKEY,
+            (KeyType::ED25519, "ssh-ed25519 AAAA".to_string()),
+        );
+        Endorsement::new_with_keys(
+            &keys.0,
+            &keys.1,
+            Utc::now() + Duration::days(1),
+            None,
+            Some(vec![(endorsed_id, claim)]),
+        )
+    }
+
+    fn id_of(keys: &(PKey<Public>, PKey<Private>)) -> Id {
+        Reid::id_from_raw_key(&keys.0.raw_public_key().unwrap())
+    }
+
+    #[test]
+    fn graph_cache_catches_up_with_appended_records() {
+        let state: AppState = test_state();
+        let (a, b, c) = (generate_ed25519(), generate_ed25519(), generate_ed25519());
+        let a_to_b: Endorsement = endorse(&a, &b);
+        state
+            .endorsements
+            .lock()
+            .unwrap()
+            .append(a_to_b.clone())
+            .unwrap();
+        assert_eq!(trust_graph(&state).endorsers_of(&id_of(&b)).len(), 1);
+
+        // An endorsement that doesn't verify is counted as seen but adds no
+        // edge
+        let b_to_c: Endorsement = endorse(&b, &c);
+        let mut forged: Endorsement = endorse(&c, &a);
+        forged.update_sig(&b.1).unwrap();
+        let revocation = EndorsementRevocation::new_with_keys(
+            &a.0,
+            &a.1,
+            &a_to_b,
+            Utc::now() - Duration::minutes(1),
+        );
+        {
+            let mut endorsements = state.endorsements.lock().unwrap();
+            endorsements.append(b_to_c.clone()).unwrap();
+            endorsements.append(forged.clone()).unwrap();
+            state
+                .revocations
+                .lock()
+                .unwrap()
+                .append(revocation.clone())
+                .unwrap();
+        }
+        let graph: TrustGraph = trust_graph(&state);
+        assert!(graph.endorsers_of(&id_of(&b)).is_empty());
+        assert_eq!(graph.endorsers_of(&id_of(&c)).len(), 1);
+        assert!(graph.endorsers_of(&id_of(&a)).is_empty());
+        {
+            let cache = state.graph.lock().unwrap();
+            assert_eq!((cache.endorsements, cache.revocations), (3, 1));
+            // Revoked edges stay in the cache, only the live graph drops them
+            assert_eq!(cache.graph.edges().count(), 2);
+        }
+
+        // Nothing new, the cache is served as is and matches a full rebuild
+        let rebuilt: TrustGraph =
+            TrustGraph::from_records(&[a_to_b, b_to_c, forged], &[revocation]);
+        let mut cached: Vec<String> = trust_graph(&state)
+            .edges()
+            .map(|edge| edge.to_string())
+            .collect();
+        let mut expected: Vec<String> = rebuilt
+            .live_at(Utc::now())
+            .edges()
+            .map(|edge| edge.to_string())
+            .collect();
+        cached.sort();
+        expected.sort();
+        assert_eq!(cached, expected);
+        let cache = state.graph.lock().unwrap();
+        assert_eq!((cache.endorsements, cache.revocations), (3, 1));
+    }
+
+    async fn publish_seq(
+        state: &AppState,
+        keys: &(PKey<Public>, PKey<Private>),
+        seq: u64,
+    ) -> StatusCode {
+        let reid_msg = ReidMessage {
+            reid: signed_reid(keys, seq),
+            pub_key: String::from_utf8(keys.0.public_key_to_pem().unwrap()).unwrap(),
+        };
+        publish(State(state.clone()), Json(reid_msg)).await
+    }
+
+    #[tokio::test]
+    async fn publish_rejects_replayed_and_older_seqs() {
+        let state: AppState = test_state();
+        let keys = generate_ed25519();
+        state
+            .log
+            .lock()
+            .unwrap()
+            .append(signed_reid(&keys, 5))
+            .unwrap();
+        assert_eq!(publish_seq(&state, &keys, 5).await, StatusCode::CONFLICT);
+        assert_eq!(publish_seq(&state, &keys, 4).await, StatusCode::CONFLICT);
+
+        // Waiting for the next group commit
+        let _committed = state.log.lock().unwrap().queue(signed_reid(&keys, 6));
+        assert_eq!(publish_seq(&state, &keys, 6).await, StatusCode::CONFLICT);
+        assert_eq!(publish_seq(&state, &keys, 5).await, StatusCode::CONFLICT);
+
+        // Taken off the queue and being written
+        let batch = state.log.lock().unwrap().take_batch(DEFAULT_COMMIT_BATCH);
+        assert_eq!(state.log.lock().unwrap().queued_len(), 0);
+        assert_eq!(publish_seq(&state, &keys, 6).await, StatusCode::CONFLICT);
+        assert_eq!(publish_seq(&state, &keys, 5).await, StatusCode::CONFLICT);
+
+        // A reid for another id queued after it doesn't hide it
+        let other = generate_ed25519();
+        let _committed = state.log.lock().unwrap().queue(signed_reid(&other, 9));
+        assert_eq!(publish_seq(&state, &keys, 6).await, StatusCode::CONFLICT);
+        assert_eq!(publish_seq(&state, &other, 9).await, StatusCode::CONFLICT);
+
+        let (written, result) = state.log.lock().unwrap().writer().write(batch.entries());
+        state
+            .log
+            .lock()
+            .unwrap()
+            .commit_batch(batch, written, result);
+        assert_eq!(publish_seq(&state, &keys, 6).await, StatusCode::CONFLICT);
+        tokio::spawn(run_group_commit(state.log.clone(), GroupCommit::default()));
+        assert_eq!(publish_seq(&state, &keys, 7).await, StatusCode::OK);
+        assert_eq!(publish_seq(&state, &keys, 7).await, StatusCode::CONFLICT);
+    }
+}
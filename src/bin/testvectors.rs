@@ -0,0 +1,188 @@
+// Prints the canonical encoding test vectors as JSON.
+// Regenerate the checked in copy with:
+//   cargo run --bin testvectors > test_vectors/canonical.json
+use chrono::{DateTime, TimeZone, Utc};
+use graphlog_proto::types::{
+    canonical::{CanonicalEncoder, FORMAT_VERSION},
+    common::{AnchorType, ClaimType, Encodable, KeyType},
+    endorsement::Endorsement,
+    log_key::LogKeyRotation,
+    merkle::{leaf_hash, SignedTreeHead},
+    reid::Reid,
+    revocation::EndorsementRevocation,
+};
+use openssl::pkey::{Id, PKey, Private};
+use serde_json::{json, Value};
+
+// Fixed Ed25519 seed so signatures in the vectors are reproducible
+const TEST_SEED: [u8; 32] = [
+    0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec, 0x2c, 0xc4,
+    0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c, 0xae, 0x7f, 0x60,
+];
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn primitives_vector() -> Value {
+    let encoding: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+        .put_u8(7)
+        .put_bool(true)
+        .put_u64(1)
+        .put_bytes(b"abc")
+        .put_str("")
+        .put_option(&None::<u8>, |enc, val| {
+            enc.put_u8(*val);
+        })
+        .put_option(&Some(2u8), |enc, val| {
+            enc.put_u8(*val);
+        })
+        .put_list(&["a", "bc"], |enc, val| {
+            enc.put_str(val);
+        })
+        .finish();
+    json!({
+        "name": "primitives",
+        "domain": "graphlog/test",
+        "fields": [
+            {"u8": 7},
+            {"bool": true},
+            {"u64": 1},
+            {"bytes": to_hex(b"abc")},
+            {"str": ""},
+            {"option": null},
+            {"option": {"u8": 2}},
+            {"list": [{"str": "a"}, {"str": "bc"}]},
+        ],
+        "encoding": to_hex(&encoding),
+    })
+}
+
+fn reid_vector(name: &str, prv_key: &PKey<Private>, mut reid: Reid) -> Value {
+    reid.update_sig(prv_key).unwrap();
+    json!({
+        "name": name,
+        "record": serde_json::to_value(&reid).unwrap(),
+        "encoding": to_hex(&reid.signable()),
+        "leaf_hash": to_hex(&leaf_hash(reid.encode().as_bytes())),
+    })
+}
+
+fn main() {
+    let prv_key: PKey<Private> = PKey::private_key_from_raw_bytes(&TEST_SEED, Id::ED25519).unwrap();
+    let pub_key_raw: Vec<u8> = prv_key.raw_public_key().unwrap();
+    let id = Reid::id_from_raw_key(&pub_key_raw);
+    let expiration: DateTime<Utc> = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+
+    let minimal = Reid::new(
+        id.clone(),
+        None,
+        expiration,
+        Vec::new(),
+        None,
+        None,
+        false,
+        pub_key_raw.clone(),
+        1,
+    );
+    let full = Reid::new(
+        id.clone(),
+        Some(vec![0, 0, 0, 42]),
+        expiration,
+        Vec::new(),
+        Some(vec![(
+            ClaimType::SSHKEY,
+            (
+                KeyType::ED25519,
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5".to_string(),
+            ),
+        )]),
+        Some(vec![
+            (AnchorType::EMAIL, "alice@example.com".to_string()),
+            (AnchorType::DNS, "example.com".to_string()),
+        ]),
+        true,
+        pub_key_raw.clone(),
+        7,
+    );
+
+    let mut endorsement = Endorsement::new(
+        id.clone(),
+        expiration,
+        None,
+        None,
+        Some(vec![(
+            leaf_hash(b"endorsed"),
+            (
+                ClaimType::SSHKEY,
+                (
+                    KeyType::ED25519,
+                    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5".to_string(),
+                ),
+            ),
+        )]),
+        pub_key_raw.clone(),
+    );
+    endorsement.update_sig(&prv_key).unwrap();
+
+    let pub_key = PKey::public_key_from_raw_bytes(&pub_key_raw, Id::ED25519).unwrap();
+    let revocation = EndorsementRevocation::new_with_keys(
+        &pub_key,
+        &prv_key,
+        &endorsement,
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+    );
+
+    let tree_head = SignedTreeHead::new(
+        2,
+        leaf_hash(b"graphlog"),
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap(),
+        &prv_key,
+    )
+    .unwrap();
+
+    // Second key from the seed with its first byte flipped
+    let mut next_seed: [u8; 32] = TEST_SEED;
+    next_seed[0] ^= 0xff;
+    let next_key = PKey::private_key_from_raw_bytes(&next_seed, Id::ED25519).unwrap();
+    let next_pub_key =
+        PKey::public_key_from_raw_bytes(&next_key.raw_public_key().unwrap(), Id::ED25519).unwrap();
+    let rotation = LogKeyRotation::new(
+        &prv_key,
+        &next_pub_key,
+        Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap(),
+    )
+    .unwrap();
+
+    let vectors = json!({
+        "format_version": FORMAT_VERSION,
+        "ed25519_seed": to_hex(&TEST_SEED),
+        "ed25519_public_key": to_hex(&pub_key_raw),
+        "vectors": [
+            primitives_vector(),
+            reid_vector("reid minimal", &prv_key, minimal),
+            reid_vector("reid with claims, anchors, pow and revoked", &prv_key, full),
+            {
+                "name": "endorsement",
+                "record": serde_json::to_value(&endorsement).unwrap(),
+                "encoding": to_hex(&endorsement.signable()),
+            },
+            {
+                "name": "endorsement revocation",
+                "record": serde_json::to_value(&revocation).unwrap(),
+                "encoding": to_hex(&revocation.signable()),
+            },
+            {
+                "name": "tree head",
+                "record": serde_json::to_value(&tree_head).unwrap(),
+                "encoding": to_hex(&tree_head.signable()),
+            },
+            {
+                "name": "log key rotation",
+                "record": serde_json::to_value(&rotation).unwrap(),
+                "encoding": to_hex(&rotation.signable()),
+            },
+        ],
+    });
+    println!("{}", serde_json::to_string_pretty(&vectors).unwrap());
+}
@@ -0,0 +1,270 @@
+use chrono::{DateTime, Utc};
+
+// Canonical byte encoding for anything that gets signed or hashed.
+//
+// Layout: version (u8) || domain (bytes) || fields...
+//   u8, bool      1 byte (bool is 0 or 1)
+//   u64, time     8 bytes big endian (time is i64 seconds since the epoch)
+//   bytes, str    u32 big endian length followed by the raw bytes
+//   option        0 for None, 1 followed by the value for Some
+//   list          u32 big endian count followed by each item
+//
+// Every variable length field carries its length so two different inputs
+// can never produce the same bytes, and the domain keeps a signature over
+// one kind of record from being replayed as another. Version 1 was the plain
+// concatenation reids were signed with before this module existed.
+pub const FORMAT_VERSION: u8 = 2;
+
+pub const REID_DOMAIN: &str = "graphlog/reid";
+pub const ENDORSEMENT_DOMAIN: &str = "graphlog/endorsement";
+pub const REVOCATION_DOMAIN: &str = "graphlog/endorsement-revocation";
+pub const TREE_HEAD_DOMAIN: &str = "graphlog/tree-head";
+pub const POW_DOMAIN: &str = "graphlog/pow";
+pub const LOG_KEY_ROTATION_DOMAIN: &str = "graphlog/log-key-rotation";
+
+pub struct CanonicalEncoder {
+    data: Vec<u8>,
+}
+
+impl CanonicalEncoder {
+    pub fn new(domain: &str) -> CanonicalEncoder {
+        let mut encoder = CanonicalEncoder {
+            data: vec![FORMAT_VERSION],
+        };
+        encoder.put_str(domain);
+        encoder
+    }
+
+    pub fn put_u8(&mut self, val: u8) -> &mut Self {
+        self.data.push(val);
+        self
+    }
+
+    pub fn put_bool(&mut self, val: bool) -> &mut Self {
+        self.put_u8(val as u8)
+    }
+
+    pub fn put_u64(&mut self, val: u64) -> &mut Self {
+        self.data.extend(val.to_be_bytes());
+        self
+    }
+
+    pub fn put_time(&mut self, val: DateTime<Utc>) -> &mut Self {
+        self.data.extend(val.timestamp().to_be_bytes());
+        self
+    }
+
+    pub fn put_bytes(&mut self, val: &[u8]) -> &mut Self {
+        self.put_len(val.len());
+        self.data.extend(val);
+        self
+    }
+
+    pub fn put_str(&mut self, val: &str) -> &mut Self {
+        self.put_bytes(val.as_bytes())
+    }
+
+    pub fn put_option<T, F>(&mut self, val: &Option<T>, put: F) -> &mut Self
+    where
+        F: FnOnce(&mut Self, &T),
+    {
+        match val {
+            None => {
+                self.put_u8(0);
+            }
+            Some(val) => {
+                self.put_u8(1);
+                put(self, val);
+            }
+        }
+        self
+    }
+
+    pub fn put_list<T, F>(&mut self, vals: &[T], mut put: F) -> &mut Self
+    where
+        F: FnMut(&mut Self, &T),
+    {
+        self.put_len(vals.len());
+        for val in vals {
+            put(self, val);
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    fn put_len(&mut self, len: usize) {
+        let len: u32 = u32::try_from(len).expect("Field too large for canonical encoding");
+        self.data.extend(len.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        common::Encodable, endorsement::Endorsement, log_key::LogKeyRotation,
+        merkle::SignedTreeHead, reid::Reid, revocation::EndorsementRevocation,
+        storage::record_leaf_hash,
+    };
+    use serde_json::Value;
+
+    // Checked in vectors, other implementations are tested against these so
+    // any change to the bytes has to be deliberate
+    const VECTORS: &str = include_str!("../../test_vectors/canonical.json");
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Puts one field described the way the primitives vector describes them
+    fn put_field(enc: &mut CanonicalEncoder, field: &Value) {
+        let (kind, val) = field.as_object().unwrap().iter().next().unwrap();
+        match kind.as_str() {
+            "u8" => {
+                enc.put_u8(val.as_u64().unwrap() as u8);
+            }
+            "bool" => {
+                enc.put_bool(val.as_bool().unwrap());
+            }
+            "u64" => {
+                enc.put_u64(val.as_u64().unwrap());
+            }
+            "bytes" => {
+                enc.put_bytes(&from_hex(val.as_str().unwrap()));
+            }
+            "str" => {
+                enc.put_str(val.as_str().unwrap());
+            }
+            "option" => {
+                let val: Option<&Value> = (!val.is_null()).then_some(val);
+                enc.put_option(&val, |enc, val| put_field(enc, val));
+            }
+            "list" => {
+                enc.put_list(val.as_array().unwrap(), put_field);
+            }
+            other => panic!("Unknown field kind {other}"),
+        }
+    }
+
+    fn record<T: serde::de::DeserializeOwned>(vector: &Value) -> T {
+        serde_json::from_value(vector["record"].clone()).unwrap()
+    }
+
+    #[test]
+    fn checked_in_vectors_match_the_current_encoding() {
+        let vectors: Value = serde_json::from_str(VECTORS).unwrap();
+        assert_eq!(vectors["format_version"], FORMAT_VERSION);
+        let vectors: &Vec<Value> = vectors["vectors"].as_array().unwrap();
+        assert_eq!(vectors.len(), 7);
+        for vector in vectors {
+            let name: &str = vector["name"].as_str().unwrap();
+            let encoding: Vec<u8> = match name {
+                "primitives" => {
+                    let mut enc = CanonicalEncoder::new(vector["domain"].as_str().unwrap());
+                    for field in vector["fields"].as_array().unwrap() {
+                        put_field(&mut enc, field);
+                    }
+                    enc.finish()
+                }
+                "reid minimal" | "reid with claims, anchors, pow and revoked" => {
+                    let reid: Reid = record(vector);
+                    // Leaves are hashed over the record as it is stored
+                    assert_eq!(
+                        to_hex(&record_leaf_hash(&reid.encode())),
+                        vector["leaf_hash"],
+                        "{name}"
+                    );
+                    reid.signable()
+                }
+                "endorsement" => record::<Endorsement>(vector).signable(),
+                "endorsement revocation" => record::<EndorsementRevocation>(vector).signable(),
+                "tree head" => record::<SignedTreeHead>(vector).signable(),
+                "log key rotation" => record::<LogKeyRotation>(vector).signable(),
+                other => panic!("No encoder for vector {other}"),
+            };
+            assert_eq!(to_hex(&encoding), vector["encoding"], "{name}");
+        }
+    }
+
+    #[test]
+    fn header_is_version_then_length_prefixed_domain() {
+        let encoding: Vec<u8> = CanonicalEncoder::new("ab").finish();
+        assert_eq!(encoding, vec![FORMAT_VERSION, 0, 0, 0, 2, b'a', b'b']);
+    }
+
+    #[test]
+    fn length_prefix_keeps_field_boundaries() {
+        // Both concatenate to "abc", only the lengths tell them apart
+        let first: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_str("ab")
+            .put_str("c")
+            .finish();
+        let second: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_str("a")
+            .put_str("bc")
+            .finish();
+        assert_ne!(first, second);
+
+        let empty_list: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_list(&[] as &[&str], |enc, val| {
+                enc.put_str(val);
+            })
+            .finish();
+        let empty_str: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_list(&[""], |enc, val| {
+                enc.put_str(val);
+            })
+            .finish();
+        assert_ne!(empty_list, empty_str);
+
+        let none: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_option(&None::<&str>, |enc, val| {
+                enc.put_str(val);
+            })
+            .finish();
+        let some_empty: Vec<u8> = CanonicalEncoder::new("graphlog/test")
+            .put_option(&Some(""), |enc, val| {
+                enc.put_str(val);
+            })
+            .finish();
+        assert_ne!(none, some_empty);
+    }
+
+    #[test]
+    fn domain_separates_identical_fields() {
+        let fields = |domain: &str| -> Vec<u8> {
+            CanonicalEncoder::new(domain)
+                .put_bytes(b"same")
+                .put_u64(1)
+                .finish()
+        };
+        let domains = [
+            REID_DOMAIN,
+            ENDORSEMENT_DOMAIN,
+            REVOCATION_DOMAIN,
+            TREE_HEAD_DOMAIN,
+            POW_DOMAIN,
+            LOG_KEY_ROTATION_DOMAIN,
+        ];
+        for (i, first) in domains.iter().enumerate() {
+            for second in &domains[i + 1..] {
+                assert_ne!(fields(first), fields(second), "{first} and {second}");
+            }
+        }
+        // The domain is length prefixed too, so it can't run into the
+        // first field
+        let short: Vec<u8> = CanonicalEncoder::new("graphlog/a").put_str("b").finish();
+        let long: Vec<u8> = CanonicalEncoder::new("graphlog/ab").put_str("").finish();
+        assert_ne!(short, long);
+    }
+}
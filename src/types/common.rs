@@ -3,6 +3,10 @@ use std::fmt;
 use chrono::{DateTime, Utc};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::policy::TrustPolicy;
+use super::storage::FsyncPolicy;
 
 pub type Id = Vec<u8>;
 pub type Key = (KeyType, String);
@@ -16,6 +20,16 @@ pub trait Decodable<T> {
     fn decode(b64: &str) -> Option<T>;
 }
 
+// Ids a log entry is filed under in the log's index, see Log::positions,
+// and any other terms it can be searched by, see Log::term_positions
+pub trait Indexable {
+    fn index_ids(&self) -> Vec<Id>;
+
+    fn search_terms(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum ClaimType {
@@ -71,6 +85,18 @@ pub enum AnchorType {
     IPADDR,
 }
 
+impl AnchorType {
+    // Form an anchor value is searched by, DNS names and email addresses
+    // don't care about case
+    pub fn normalize(&self, value: &str) -> String {
+        match self {
+            Self::DNS => value.trim().trim_end_matches('.').to_lowercase(),
+            Self::EMAIL => value.trim().to_lowercase(),
+            Self::PHONE | Self::IPADDR => value.trim().to_string(),
+        }
+    }
+}
+
 impl fmt::Display for AnchorType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
@@ -110,6 +136,7 @@ pub struct Config {
     pub client_conf: Option<ClientConfig>,
     #[serde(rename = "server")]
     pub server_conf: Option<ServerConfig>,
+    pub policies: Option<HashMap<String, TrustPolicy>>, // trust policies by name
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -119,10 +146,37 @@ pub struct ClientConfig {
     pub expiration: DateTime<Utc>,
     pub claims: Option<Vec<(ClaimType, Key)>>,
     pub anchors: Option<Vec<(AnchorType, String)>>,
+    #[serde(default)]
+    pub seq: u64, // sequence number of the last reid the log accepted from us
+    pub log_key: Option<String>, // base64 log key pinned by the log-key command
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// The [server] table of the config file the server is started with, e.g.
+//
+//   [server]
+//   addr = "0.0.0.0:7878"
+//   persist_path = "/var/lib/graphlog/reids"
+//   pow_difficulty = 16
+//   fsync = "periodic:1000"
+//   log_key_path = "/etc/graphlog/log-key.pem"
+//
+// Anything left out takes its default, see bin/server.rs
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ServerConfig {
+    #[serde(default = "default_server_addr")]
     pub addr: String,
-    pub persist_path: Option<String>,
+    pub persist_path: Option<String>, // None only keeps the logs in memory
+    pub pow_difficulty: Option<u8>,   // leading zero bits required, None or 0 disables
+    pub segment_bytes: Option<u64>,   // size log segments roll over at, None uses the default
+    pub fsync: Option<FsyncPolicy>,   // None fsyncs once per batch
+    pub commit_batch: Option<usize>,  // most appends written together in one group commit
+    pub commit_delay_ms: Option<u64>, // longest an append waits for others to join its batch
+    pub max_range_limit: Option<usize>, // most entries a range or tail request returns
+    pub max_body_bytes: Option<usize>, // largest request body accepted
+    pub log_key_path: Option<String>, // PEM Ed25519 log key, generated if missing, None
+                                      // keeps it next to persist_path
+}
+
+pub fn default_server_addr() -> String {
+    String::from("127.0.0.1:7878")
 }
@@ -1,19 +1,28 @@
-use super::common::{Id, Key, Sig};
+use super::canonical::{CanonicalEncoder, ENDORSEMENT_DOMAIN};
+use super::common::{id_equal, ClaimType, Decodable, Encodable, Id, Indexable, Key, Sig};
+use super::pow;
+use super::reid::Reid;
 use chrono::serde::ts_seconds;
 use chrono::{DateTime, Utc};
-use openssl::hash::MessageDigest;
-use openssl::pkey::{PKey, Private};
-use openssl::sign::Signer;
+use openssl::base64::{decode_block, encode_block};
+use openssl::error::ErrorStack;
+use openssl::pkey::{self, PKey, Private, Public};
+use openssl::sha::sha256;
+use openssl::sign::{Signer, Verifier};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Serialize, Deserialize)]
-struct Endorsement {
-    endorsing_id: Id,
+// One identity vouching that the listed claims belong to the listed ids
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Endorsement {
+    endorsing_id: Id, // Hash of the endorser's public key
     #[serde(with = "ts_seconds")]
     expiration: DateTime<Utc>,
     sig: Option<Sig>,
     pow: Option<Vec<u8>>,
-    endorsements: Option<Vec<(Id, (String, Key))>>,
+    endorsements: Option<Vec<(Id, (ClaimType, Key))>>, // (endorsed id, endorsed claim)
+    #[serde(default)]
+    pub_key: Vec<u8>, // raw Ed25519 public key that endorsing_id is the hash of
 }
 
 impl Endorsement {
@@ -22,7 +31,8 @@ impl Endorsement {
         expiration: DateTime<Utc>,
         sig: Option<Sig>,
         pow: Option<Vec<u8>>,
-        endorsements: Option<Vec<(Id, (String, Key))>>,
+        endorsements: Option<Vec<(Id, (ClaimType, Key))>>,
+        pub_key: Vec<u8>,
     ) -> Self {
         Self {
             endorsing_id,
@@ -30,52 +40,256 @@ impl Endorsement {
             sig,
             pow,
             endorsements,
+            pub_key,
         }
     }
 
-    fn args_to_signable () {
-        // TODO Implement: move the logic for converting the
-        // data into a signable format into this funciton then
-        // create a sign and verify funciton that calls this funciton
-        // similar to the patterin reid.rs
+    pub fn new_with_keys(
+        pub_key: &PKey<Public>,
+        prv_key: &PKey<Private>,
+        expiration: DateTime<Utc>,
+        pow: Option<Vec<u8>>,
+        endorsements: Option<Vec<(Id, (ClaimType, Key))>>,
+    ) -> Self {
+        let pub_key_raw: Vec<u8> = match pub_key.raw_public_key() {
+            Err(why) => {
+                panic!("Couldn't convert public key into raw bytes using raw_public_key: {why}")
+            }
+            Ok(pub_key_raw) => pub_key_raw,
+        };
+        let mut endorsement = Self {
+            endorsing_id: Reid::id_from_raw_key(&pub_key_raw),
+            expiration,
+            sig: None,
+            pow,
+            endorsements,
+            pub_key: pub_key_raw,
+        };
+        if let Err(why) = endorsement.update_sig(prv_key) {
+            panic!("Signing Endorsement failed: {why}");
+        }
+        endorsement
+    }
+
+    pub fn append_endorsement(&mut self, endorsed_id: Id, claim_type: ClaimType, claim_value: Key) {
+        self.endorsements
+            .get_or_insert_with(Vec::new)
+            .push((endorsed_id, (claim_type, claim_value)));
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self).unwrap()
+    }
+
+    pub fn get_endorsing_id(&self) -> Id {
+        self.endorsing_id.clone()
+    }
+
+    pub fn get_expiration(&self) -> DateTime<Utc> {
+        self.expiration
+    }
+
+    pub fn get_endorsements(&self) -> &Option<Vec<(Id, (ClaimType, Key))>> {
+        &self.endorsements
+    }
+
+    // Public key embedded in the endorsement, None if missing or invalid
+    pub fn public_key(&self) -> Option<PKey<Public>> {
+        if self.pub_key.is_empty() {
+            return None;
+        }
+        PKey::public_key_from_raw_bytes(&self.pub_key, pkey::Id::ED25519).ok()
+    }
+
+    // Checks that endorsing_id is the hash of pub_key and that the embedded
+    // key, if there is one, is that same key
+    pub fn verify_binding(&self, pub_key: &PKey<Public>) -> bool {
+        let pub_key_raw: Vec<u8> = match pub_key.raw_public_key() {
+            Err(_) => return false,
+            Ok(pub_key_raw) => pub_key_raw,
+        };
+        if !self.pub_key.is_empty() && self.pub_key != pub_key_raw {
+            return false;
+        }
+        id_equal(
+            self.endorsing_id.clone(),
+            Reid::id_from_raw_key(&pub_key_raw),
+        )
+    }
+
+    // Self contained check using only the embedded public key
+    pub fn verify(&self) -> bool {
+        match self.public_key() {
+            None => false,
+            Some(pub_key) => self.verify_binding(&pub_key) && self.verify_sig(&pub_key),
+        }
     }
 
-    pub fn verify_sig() {
+    pub fn update_sig(&mut self, prv_key: &PKey<Private>) -> std::result::Result<Sig, ErrorStack> {
+        let sig: Sig = Endorsement::sign_endorsement(prv_key, self)?;
+        self.sig = Some(sig.clone());
+        Ok(sig)
     }
 
     fn sign_endorsement(
-        prv_key: PKey<Private>,
+        prv_key: &PKey<Private>,
+        endorsement: &Endorsement,
+    ) -> std::result::Result<Sig, ErrorStack> {
+        let mut signer = Signer::new_without_digest(prv_key)?;
+        let end_data: Vec<u8> = Endorsement::endorsement_to_signable(endorsement);
+        signer.sign_oneshot_to_vec(&end_data)
+    }
+
+    pub fn verify_sig(&self, pub_key: &PKey<Public>) -> bool {
+        let sig: &Sig = match &self.sig {
+            None => return false,
+            Some(sig) => sig,
+        };
+        let mut verify = Verifier::new_without_digest(pub_key).unwrap();
+        let data: Vec<u8> = Endorsement::endorsement_to_signable(self);
+        verify.verify_oneshot(sig, &data).unwrap_or(false)
+    }
+
+    // The proof of work covers everything the signature does except the
+    // proof itself, the signature then covers the proof
+    pub fn pow_preimage(&self) -> Vec<u8> {
+        Endorsement::args_to_signable(
+            &self.endorsing_id,
+            &self.pub_key,
+            self.expiration,
+            &None,
+            &self.endorsements,
+        )
+    }
+
+    pub fn solve_pow(
+        &mut self,
+        difficulty: u8,
+        prv_key: &PKey<Private>,
+    ) -> std::result::Result<Sig, ErrorStack> {
+        self.pow = Some(pow::solve(&self.pow_preimage(), difficulty));
+        self.update_sig(prv_key)
+    }
+
+    pub fn verify_pow(&self, difficulty: u8) -> bool {
+        match &self.pow {
+            None => difficulty == 0,
+            Some(nonce) => pow::verify(&self.pow_preimage(), nonce, difficulty),
+        }
+    }
+
+    // Bytes covered by the signature
+    pub fn signable(&self) -> Vec<u8> {
+        Endorsement::endorsement_to_signable(self)
+    }
+
+    // What revocations refer to the endorsement by
+    pub fn digest(&self) -> Vec<u8> {
+        sha256(&self.signable()).to_vec()
+    }
+
+    fn endorsement_to_signable(endorsement: &Endorsement) -> Vec<u8> {
+        Endorsement::args_to_signable(
+            &endorsement.endorsing_id,
+            &endorsement.pub_key,
+            endorsement.expiration,
+            &endorsement.pow,
+            &endorsement.endorsements,
+        )
+    }
+
+    // See canonical.rs for the byte layout
+    fn args_to_signable(
         endorsing_id: &Id,
+        pub_key: &[u8],
         expiration: DateTime<Utc>,
-        endorsements: &Option<Vec<(Id, (String, Key))>>,
-    ) -> std::result::Result<Sig, openssl::error::ErrorStack> {
-        let mut signer = Signer::new(MessageDigest::sha256(), &prv_key).unwrap();
-        let mut end_data: Vec<u8> = Vec::new();
-
-        end_data.extend(endorsing_id.clone().iter());
-
-        let exp_raw: Vec<u8> = expiration.clone().to_rfc3339().into_bytes();
-        end_data.extend(exp_raw);
-
-        let endorsements_clone: Option<Vec<(Id, (String, Key))>> = endorsements.clone();
-        if let Some(endorsement_vals) = endorsements_clone {
-            let endorsement_raw: Vec<u8> = endorsement_vals
-                .into_iter()
-                .flat_map(|(i, (s, k))| {
-                    let mut combined = Vec::new();
-                    combined.extend(i);
-                    combined.extend(s.clone().into_bytes());
-                    combined.push(k.0 as u8); // Key type
-                    combined.extend(k.1.clone().into_bytes()); // Key value
-                    combined
-                })
-                .collect();
-            end_data.extend(endorsement_raw);
-        };
+        pow: &Option<Vec<u8>>,
+        endorsements: &Option<Vec<(Id, (ClaimType, Key))>>,
+    ) -> Vec<u8> {
+        CanonicalEncoder::new(ENDORSEMENT_DOMAIN)
+            .put_bytes(endorsing_id)
+            .put_bytes(pub_key)
+            .put_time(expiration)
+            .put_option(endorsements, |enc, endorsements| {
+                enc.put_list(endorsements, |enc, (i, (ct, k))| {
+                    enc.put_bytes(i)
+                        .put_u8(ct.clone() as u8)
+                        .put_u8(k.0.clone() as u8) // Key type
+                        .put_str(&k.1); // Key value
+                });
+            })
+            .put_option(pow, |enc, pow| {
+                enc.put_bytes(pow);
+            })
+            .finish()
+    }
+}
+
+// Filed under the endorser and every identity it endorses
+impl Indexable for Endorsement {
+    fn index_ids(&self) -> Vec<Id> {
+        let mut ids: Vec<Id> = vec![self.endorsing_id.clone()];
+        ids.extend(self.endorsements.iter().flatten().map(|(id, _)| id.clone()));
+        ids
+    }
+}
+
+impl Encodable for Endorsement {
+    fn encode(&self) -> String {
+        encode_block(self.to_json().as_bytes())
+    }
+}
 
-        if let Err(why) = signer.update(&end_data) {
-            panic!("Signer failed somehow: {why}")
+impl Decodable<Endorsement> for Endorsement {
+    fn decode(endorsement_b64: &str) -> Option<Endorsement> {
+        let endorsement_vec: Vec<u8> = match decode_block(endorsement_b64) {
+            Err(why) => {
+                println!("Error decoding endorsement base64: {why}");
+                return None;
+            }
+            Ok(vec) => vec,
         };
-        signer.sign_to_vec()
+        let endorsement_json: String = match String::from_utf8(endorsement_vec) {
+            Err(why) => {
+                println!("Error parsing decoded base64 vector into string: {why}");
+                return None;
+            }
+            Ok(str) => str,
+        };
+        match serde_json::from_str(&endorsement_json) {
+            Err(why) => {
+                println!("Error decoding endorsement_json into Endorsement object: {why}");
+                None
+            }
+            Ok(endorsement) => endorsement,
+        }
+    }
+}
+
+impl fmt::Display for Endorsement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // endorsing id
+        writeln!(f, "endorsing id: {}", encode_block(&self.endorsing_id))?;
+
+        // expiration
+        writeln!(f, "expiration: {}", self.expiration.to_rfc3339())?;
+
+        // endorsements
+        writeln!(f, "endorsements:")?;
+        match &self.endorsements {
+            Some(endorsements) => {
+                for (id, (claim_type, key)) in endorsements {
+                    writeln!(
+                        f,
+                        "- {}: {}: {}",
+                        encode_block(id),
+                        claim_type,
+                        Reid::key_to_pem(key)
+                    )?;
+                }
+            }
+            None => writeln!(f, "None")?,
+        }
+        Ok(())
     }
 }
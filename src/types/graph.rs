@@ -0,0 +1,961 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use openssl::base64::encode_block;
+use serde::{Deserialize, Serialize};
+
+use super::common::{AnchorType, ClaimType, Id, Key};
+use super::endorsement::Endorsement;
+use super::reid::Reid;
+use super::revocation::EndorsementRevocation;
+
+// `from` endorsed `claims` as belonging to `to`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrustEdge {
+    pub from: Id,
+    pub to: Id,
+    pub claims: Vec<(ClaimType, Key)>,
+    #[serde(with = "ts_seconds")]
+    pub expiration: DateTime<Utc>,
+    pub endorsement: Vec<u8>, // digest of the endorsement the edge came from
+    #[serde(default, with = "ts_seconds_option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl TrustEdge {
+    pub fn is_revoked_at(&self, at: DateTime<Utc>) -> bool {
+        self.revoked_at.is_some_and(|revoked_at| revoked_at <= at)
+    }
+
+    // Neither expired nor revoked at `at`
+    pub fn live_at(&self, at: DateTime<Utc>) -> bool {
+        self.expiration > at && !self.is_revoked_at(at)
+    }
+}
+
+impl fmt::Display for TrustEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} (expires {})",
+            encode_block(&self.from),
+            encode_block(&self.to),
+            self.expiration.to_rfc3339()
+        )?;
+        if let Some(revoked_at) = self.revoked_at {
+            write!(f, " (revoked {})", revoked_at.to_rfc3339())?;
+        }
+        for (claim_type, key) in &self.claims {
+            write!(f, "\n  - {}: {}", claim_type, Reid::key_to_pem(key))?;
+        }
+        Ok(())
+    }
+}
+
+// Formats the graph can be exported to for visualization
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    Dot,
+    Graphml,
+}
+
+// Anchors from each identity's latest reid, used to label exported nodes
+pub type NodeLabels = HashMap<Id, Vec<(AnchorType, String)>>;
+
+// Directed graph of endorsements keyed by Id, an edge A -> B means A
+// vouches for some of B's claims
+#[derive(Default)]
+pub struct TrustGraph {
+    out_edges: HashMap<Id, Vec<TrustEdge>>,
+    in_edges: HashMap<Id, Vec<TrustEdge>>,
+    endorsed: HashMap<Vec<u8>, Vec<Id>>, // ids each endorsement has an edge to, by its digest
+}
+
+impl TrustGraph {
+    pub fn new() -> TrustGraph {
+        TrustGraph {
+            out_edges: HashMap::new(),
+            in_edges: HashMap::new(),
+            endorsed: HashMap::new(),
+        }
+    }
+
+    // Endorsements that don't verify against their embedded key are skipped
+    pub fn from_endorsements<'a, I>(endorsements: I) -> TrustGraph
+    where
+        I: IntoIterator<Item = &'a Endorsement>,
+    {
+        let mut graph = TrustGraph::new();
+        for endorsement in endorsements {
+            graph.add_endorsement(endorsement);
+        }
+        graph
+    }
+
+    // Same as from_endorsements with the revocations marked on the edges
+    // they withdraw, revoked edges stay in the graph so it can still be
+    // evaluated as of a time before the revocation
+    pub fn from_records<'a, E, R>(endorsements: E, revocations: R) -> TrustGraph
+    where
+        E: IntoIterator<Item = &'a Endorsement>,
+        R: IntoIterator<Item = &'a EndorsementRevocation>,
+    {
+        let mut graph = TrustGraph::from_endorsements(endorsements);
+        for revocation in revocations {
+            graph.add_revocation(revocation);
+        }
+        graph
+    }
+
+    // Adds one edge per endorsed id, returns false if the endorsement is invalid
+    pub fn add_endorsement(&mut self, endorsement: &Endorsement) -> bool {
+        if !endorsement.verify() {
+            return false;
+        }
+        self.add_verified_endorsement(endorsement);
+        true
+    }
+
+    // Same as add_endorsement for an endorsement the caller has already
+    // verified
+    pub fn add_verified_endorsement(&mut self, endorsement: &Endorsement) {
+        let mut claims_by_id: Vec<(Id, Vec<(ClaimType, Key)>)> = Vec::new();
+        for (id, claim) in endorsement.get_endorsements().iter().flatten() {
+            match claims_by_id.iter_mut().find(|(i, _)| i == id) {
+                Some((_, claims)) => claims.push(claim.clone()),
+                None => claims_by_id.push((id.clone(), vec![claim.clone()])),
+            }
+        }
+        for (to, claims) in claims_by_id {
+            self.add_edge(TrustEdge {
+                from: endorsement.get_endorsing_id(),
+                to,
+                claims,
+                expiration: endorsement.get_expiration(),
+                endorsement: endorsement.digest(),
+                revoked_at: None,
+            });
+        }
+    }
+
+    // Marks every edge from the revoked endorsement, returns false if the
+    // revocation is invalid
+    pub fn add_revocation(&mut self, revocation: &EndorsementRevocation) -> bool {
+        if !revocation.verify() {
+            return false;
+        }
+        self.add_verified_revocation(revocation);
+        true
+    }
+
+    // Same as add_revocation for a revocation the caller has already verified
+    pub fn add_verified_revocation(&mut self, revocation: &EndorsementRevocation) {
+        let revoked_at: DateTime<Utc> = revocation.get_revoked_at();
+        let from: Id = revocation.get_endorsing_id();
+        let digest: &[u8] = revocation.get_endorsement();
+        let mark = |edge: &mut TrustEdge| {
+            if edge.from == from && edge.endorsement == digest {
+                edge.revoked_at = Some(match edge.revoked_at {
+                    Some(earlier) if earlier < revoked_at => earlier,
+                    _ => revoked_at,
+                });
+            }
+        };
+        let Some(endorsed) = self.endorsed.get(digest) else {
+            return;
+        };
+        // Each edge is kept under both of its ends
+        for edge in self.out_edges.get_mut(&from).into_iter().flatten() {
+            mark(edge);
+        }
+        for to in endorsed {
+            for edge in self.in_edges.get_mut(to).into_iter().flatten() {
+                mark(edge);
+            }
+        }
+    }
+
+    pub fn add_edge(&mut self, edge: TrustEdge) {
+        self.endorsed
+            .entry(edge.endorsement.clone())
+            .or_default()
+            .push(edge.to.clone());
+        self.in_edges
+            .entry(edge.to.clone())
+            .or_default()
+            .push(edge.clone());
+        self.out_edges
+            .entry(edge.from.clone())
+            .or_default()
+            .push(edge);
+    }
+
+    // Copy of the graph with only the edges that are neither expired nor
+    // revoked at `at`
+    pub fn live_at(&self, at: DateTime<Utc>) -> TrustGraph {
+        self.filtered(|edge| edge.live_at(at))
+    }
+
+    // Copy of the graph with only the edges `keep` returns true for
+    pub fn filtered<F>(&self, mut keep: F) -> TrustGraph
+    where
+        F: FnMut(&TrustEdge) -> bool,
+    {
+        let mut graph = TrustGraph::new();
+        for edge in self.edges() {
+            if keep(edge) {
+                graph.add_edge(edge.clone());
+            }
+        }
+        graph
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &TrustEdge> {
+        self.out_edges.values().flatten()
+    }
+
+    pub fn nodes(&self) -> HashSet<Id> {
+        self.out_edges
+            .keys()
+            .chain(self.in_edges.keys())
+            .cloned()
+            .collect()
+    }
+
+    // Who endorses id
+    pub fn endorsers_of(&self, id: &Id) -> &[TrustEdge] {
+        self.in_edges.get(id).map_or(&[], |edges| edges.as_slice())
+    }
+
+    // Who id endorses
+    pub fn endorsed_by(&self, id: &Id) -> &[TrustEdge] {
+        self.out_edges.get(id).map_or(&[], |edges| edges.as_slice())
+    }
+
+    // Fewest hops chain of endorsements leading from `from` to `to`
+    pub fn shortest_path(&self, from: &Id, to: &Id) -> Option<Vec<TrustEdge>> {
+        self.shortest_path_avoiding(from, to, &HashSet::new(), usize::MAX)
+    }
+
+    // Same as shortest_path but never passes through an id in `avoid` and
+    // gives up on paths longer than `max_hops` endorsements
+    pub fn shortest_path_avoiding(
+        &self,
+        from: &Id,
+        to: &Id,
+        avoid: &HashSet<Id>,
+        max_hops: usize,
+    ) -> Option<Vec<TrustEdge>> {
+        if from == to {
+            return Some(Vec::new());
+        }
+        let mut parent: HashMap<Id, &TrustEdge> = HashMap::new();
+        let mut visited: HashSet<Id> = HashSet::from([from.clone()]);
+        let mut queue: VecDeque<(Id, usize)> = VecDeque::from([(from.clone(), 0)]);
+        while let Some((id, hops)) = queue.pop_front() {
+            if hops >= max_hops {
+                continue;
+            }
+            for edge in self.endorsed_by(&id) {
+                if (&edge.to != to && avoid.contains(&edge.to)) || !visited.insert(edge.to.clone())
+                {
+                    continue;
+                }
+                parent.insert(edge.to.clone(), edge);
+                if &edge.to == to {
+                    let mut path: Vec<TrustEdge> = Vec::new();
+                    let mut cur: &Id = to;
+                    while let Some(edge) = parent.get(cur) {
+                        path.push((*edge).clone());
+                        cur = &edge.from;
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                queue.push_back((edge.to.clone(), hops + 1));
+            }
+        }
+        None
+    }
+
+    // Every identity reachable from `roots` in at most `hops` endorsements,
+    // with the number of hops it took to reach it
+    pub fn within_hops(&self, roots: &[Id], hops: usize) -> Vec<(Id, usize)> {
+        let mut reached: Vec<(Id, usize)> = Vec::new();
+        let mut visited: HashSet<Id> = HashSet::new();
+        let mut queue: VecDeque<(Id, usize)> = VecDeque::new();
+        for root in roots {
+            if visited.insert(root.clone()) {
+                queue.push_back((root.clone(), 0));
+            }
+        }
+        while let Some((id, dist)) = queue.pop_front() {
+            if dist < hops {
+                for edge in self.endorsed_by(&id) {
+                    if visited.insert(edge.to.clone()) {
+                        queue.push_back((edge.to.clone(), dist + 1));
+                    }
+                }
+            }
+            reached.push((id, dist));
+        }
+        reached
+    }
+
+    pub fn export(&self, format: GraphFormat, labels: &NodeLabels) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(labels),
+            GraphFormat::Graphml => self.to_graphml(labels),
+        }
+    }
+
+    // Graphviz DOT, nodes are labelled with their id and anchors and edges
+    // with the endorsed claim types and expiration, full claims go in the
+    // edge tooltip
+    pub fn to_dot(&self, labels: &NodeLabels) -> String {
+        let mut dot = String::from("digraph graphlog {\n    node [shape=box];\n");
+        for id in self.sorted_nodes() {
+            let mut label: String = encode_block(&id);
+            for (anchor_type, anchor) in labels.get(&id).into_iter().flatten() {
+                label.push_str(&format!("\n{anchor_type:?}: {anchor}"));
+            }
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\"];\n",
+                encode_block(&id),
+                dot_escape(&label)
+            ));
+        }
+        for edge in self.sorted_edges() {
+            let claim_types: Vec<String> = edge
+                .claims
+                .iter()
+                .map(|(claim_type, _)| format!("{claim_type:?}"))
+                .collect();
+            let label: String = format!(
+                "{}\nexpires {}",
+                claim_types.join(", "),
+                edge.expiration.to_rfc3339()
+            );
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\", tooltip=\"{}\"];\n",
+                encode_block(&edge.from),
+                encode_block(&edge.to),
+                dot_escape(&label),
+                dot_escape(&claims_text(&edge.claims))
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_graphml(&self, labels: &NodeLabels) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"anchors\" for=\"node\" attr.name=\"anchors\" attr.type=\"string\"/>\n",
+            "  <key id=\"expiration\" for=\"edge\" attr.name=\"expiration\" attr.type=\"string\"/>\n",
+            "  <key id=\"claims\" for=\"edge\" attr.name=\"claims\" attr.type=\"string\"/>\n",
+            "  <graph id=\"graphlog\" edgedefault=\"directed\">\n",
+        ));
+        for id in self.sorted_nodes() {
+            let anchors: Vec<String> = labels
+                .get(&id)
+                .into_iter()
+                .flatten()
+                .map(|(anchor_type, anchor)| format!("{anchor_type:?}: {anchor}"))
+                .collect();
+            xml.push_str(&format!(
+                "    <node id=\"{}\">\n      <data key=\"anchors\">{}</data>\n    </node>\n",
+                xml_escape(&encode_block(&id)),
+                xml_escape(&anchors.join("\n"))
+            ));
+        }
+        for edge in self.sorted_edges() {
+            xml.push_str(&format!(
+                concat!(
+                    "    <edge source=\"{}\" target=\"{}\">\n",
+                    "      <data key=\"expiration\">{}</data>\n",
+                    "      <data key=\"claims\">{}</data>\n",
+                    "    </edge>\n"
+                ),
+                xml_escape(&encode_block(&edge.from)),
+                xml_escape(&encode_block(&edge.to)),
+                edge.expiration.to_rfc3339(),
+                xml_escape(&claims_text(&edge.claims))
+            ));
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+
+    // Exports are sorted so the same graph always produces the same file
+    fn sorted_nodes(&self) -> Vec<Id> {
+        let mut nodes: Vec<Id> = self.nodes().into_iter().collect();
+        nodes.sort();
+        nodes
+    }
+
+    fn sorted_edges(&self) -> Vec<&TrustEdge> {
+        let mut edges: Vec<&TrustEdge> = self.edges().collect();
+        edges.sort_by(|a, b| (&a.from, &a.to, a.expiration).cmp(&(&b.from, &b.to, b.expiration)));
+        edges
+    }
+}
+
+fn claims_text(claims: &[(ClaimType, Key)]) -> String {
+    let claims: Vec<String> = claims
+        .iter()
+        .map(|(claim_type, key)| format!("{claim_type:?}: {}", Reid::key_to_pem(key).trim()))
+        .collect();
+    claims.join("\n")
+}
+
+fn dot_escape(val: &str) -> String {
+    val.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn xml_escape(val: &str) -> String {
+    val.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::common::KeyType;
+    use crate::utils::keys::generate_ed25519;
+    use chrono::Duration;
+    use openssl::pkey::{PKey, Private, Public};
+
+    fn id(n: u8) -> Id {
+        vec![n; 32]
+    }
+
+    fn edge(from: u8, to: u8) -> TrustEdge {
+        TrustEdge {
+            from: id(from),
+            to: id(to),
+            claims: vec![(ClaimType::SSHKEY, (KeyType::ED25519, format!("key-{to}")))],
+            expiration: Utc::now() + Duration::days(365),
+            endorsement: vec![from, to],
+            revoked_at: None,
+        }
+    }
+
+    // 1 reaches 4 through 2, through 3, or the long way through 5 and 6,
+    // and 4 endorses 1 back
+    fn diamond() -> TrustGraph {
+        let mut graph = TrustGraph::new();
+        for (from, to) in [
+            (1, 2),
+            (2, 4),
+            (1, 3),
+            (3, 4),
+            (1, 5),
+            (5, 6),
+            (6, 4),
+            (4, 1),
+        ] {
+            graph.add_edge(edge(from, to));
+        }
+        graph
+    }
+
+    fn hops(path: &[TrustEdge]) -> Vec<(u8, u8)> {
+        path.iter().map(|edge| (edge.from[0], edge.to[0])).collect()
+    }
+
+    fn avoiding(ids: &[u8]) -> HashSet<Id> {
+        ids.iter().map(|n| id(*n)).collect()
+    }
+
+    #[test]
+    fn shortest_path_takes_the_fewest_hops() {
+        let graph: TrustGraph = diamond();
+        let path: Vec<TrustEdge> = graph.shortest_path(&id(1), &id(4)).unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[0].from, id(1));
+        assert_eq!(path[1].to, id(4));
+        assert_eq!(graph.shortest_path(&id(1), &id(1)).unwrap().len(), 0);
+        assert!(graph.shortest_path(&id(1), &id(7)).is_none());
+    }
+
+    #[test]
+    fn shortest_path_avoiding_routes_around_ids() {
+        let graph: TrustGraph = diamond();
+        let path = graph.shortest_path_avoiding(&id(1), &id(4), &avoiding(&[2]), usize::MAX);
+        assert_eq!(hops(&path.unwrap()), vec![(1, 3), (3, 4)]);
+        let path = graph.shortest_path_avoiding(&id(1), &id(4), &avoiding(&[2, 3]), usize::MAX);
+        assert_eq!(hops(&path.unwrap()), vec![(1, 5), (5, 6), (6, 4)]);
+        assert!(graph
+            .shortest_path_avoiding(&id(1), &id(4), &avoiding(&[2, 3, 6]), usize::MAX)
+            .is_none());
+        // The destination itself is never avoided
+        let path = graph.shortest_path_avoiding(&id(1), &id(4), &avoiding(&[4]), usize::MAX);
+        assert_eq!(path.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn shortest_path_avoiding_stops_at_max_hops() {
+        let graph: TrustGraph = diamond();
+        let avoid: HashSet<Id> = avoiding(&[2, 3]);
+        assert!(graph
+            .shortest_path_avoiding(&id(1), &id(4), &avoid, 2)
+            .is_none());
+        let path = graph.shortest_path_avoiding(&id(1), &id(4), &avoid, 3);
+        assert_eq!(path.unwrap().len(), 3);
+        assert!(graph
+            .shortest_path_avoiding(&id(1), &id(2), &HashSet::new(), 0)
+            .is_none());
+    }
+
+    #[test]
+    fn within_hops_counts_the_shortest_distance() {
+        let graph: TrustGraph = diamond();
+        let reached = |roots: &[u8], max: usize| -> Vec<(u8, usize)> {
+            let roots: Vec<Id> = roots.iter().map(|n| id(*n)).collect();
+            let mut reached: Vec<(u8, usize)> = graph
+                .within_hops(&roots, max)
+                .into_iter()
+                .map(|(id, dist)| (id[0], dist))
+                .collect();
+            reached.sort();
+            reached
+        };
+        assert_eq!(reached(&[1], 0), vec![(1, 0)]);
+        assert_eq!(reached(&[1], 1), vec![(1, 0), (2, 1), (3, 1), (5, 1)]);
+        // The cycle back to 1 doesn't revisit it
+        assert_eq!(
+            reached(&[1], 5),
+            vec![(1, 0), (2, 1), (3, 1), (4, 2), (5, 1), (6, 2)]
+        );
+        // Every root starts at 0, repeated roots only once
+        assert_eq!(reached(&[5, 6, 5], 1), vec![(4, 1), (5, 0), (6, 0)]);
+        assert_eq!(reached(&[7], 3), vec![(7, 0)]);
+    }
+
+    type Keys = (PKey<Public>, PKey<Private>);
+
+    fn id_of(keys: &Keys) -> Id {
+        Reid::id_from_raw_key(&keys.0.raw_public_key().unwrap())
+    }
+
+    // Signed endorsement of one claim for each of `endorsed`
+    fn endorse(keys: &Keys, endorsed: &[&Keys]) -> Endorsement {
+        let claims: Vec<(Id, (ClaimType, Key))> = endorsed
+            .iter()
+            .map(|to| {
+                let key: Key = (KeyType::ED25519, "ssh-ed25519 AAAA".to_string());
+                (id_of(to), (ClaimType::SSHKEY, key))
+            })
+            .collect();
+        Endorsement::new_with_keys(
+            &keys.0,
+            &keys.1,
+            Utc::now() + Duration::days(365),
+            None,
+            Some(claims),
+        )
+    }
+
+    fn revoke(keys: &Keys, endorsement: &Endorsement, at: DateTime<Utc>) -> EndorsementRevocation {
+        EndorsementRevocation::new_with_keys(&keys.0, &keys.1, endorsement, at)
+    }
+
+    #[test]
+    fn revoked_endorsement_stops_counting() {
+        let (a, b, c) = (generate_ed25519(), generate_ed25519(), generate_ed25519());
+        let revoked: Endorsement = endorse(&a, &[&b, &c]);
+        let kept: Endorsement = endorse(&a, &[&c]);
+        let revoked_at: DateTime<Utc> = Utc::now() - Duration::hours(1);
+        let graph = TrustGraph::from_records(
+            &[revoked.clone(), kept],
+            &[revoke(&a, &revoked, revoked_at)],
+        );
+
+        let live: TrustGraph = graph.live_at(Utc::now());
+        assert!(live.endorsers_of(&id_of(&b)).is_empty());
+        // Only the edge from the other endorsement of c is left
+        assert_eq!(live.endorsers_of(&id_of(&c)).len(), 1);
+        assert_eq!(live.endorsed_by(&id_of(&a)).len(), 1);
+        // Both ends of a revoked edge agree on when it was revoked
+        for edge in graph
+            .endorsers_of(&id_of(&b))
+            .iter()
+            .chain(graph.endorsed_by(&id_of(&a)))
+        {
+            let revoked_edge: bool = edge.endorsement == revoked.digest();
+            assert_eq!(edge.revoked_at, revoked_edge.then_some(revoked_at));
+        }
+    }
+
+    #[test]
+    fn revocation_only_counts_from_its_timestamp() {
+        let (a, b) = (generate_ed25519(), generate_ed25519());
+        let endorsement: Endorsement = endorse(&a, &[&b]);
+        let revoked_at: DateTime<Utc> = Utc::now() + Duration::hours(1);
+        let graph =
+            TrustGraph::from_records([&endorsement], &[revoke(&a, &endorsement, revoked_at)]);
+        assert_eq!(graph.live_at(Utc::now()).endorsers_of(&id_of(&b)).len(), 1);
+        assert!(graph
+            .live_at(revoked_at)
+            .endorsers_of(&id_of(&b))
+            .is_empty());
+    }
+
+    #[test]
+    fn earliest_revocation_wins() {
+        let (a, b) = (generate_ed25519(), generate_ed25519());
+        let endorsement: Endorsement = endorse(&a, &[&b]);
+        let earlier: DateTime<Utc> = Utc::now() - Duration::hours(2);
+        let later: DateTime<Utc> = Utc::now() + Duration::hours(2);
+        let revocations = [
+            revoke(&a, &endorsement, earlier),
+            revoke(&a, &endorsement, later),
+        ];
+        // A revocation dated later can't push back one already in force, in
+        // whichever order they are added
+        for order in [[0, 1], [1, 0]] {
+            let graph =
+                TrustGraph::from_records([&endorsement], order.iter().map(|i| &revocations[*i]));
+            let edge: &TrustEdge = &graph.endorsers_of(&id_of(&b))[0];
+            assert_eq!(edge.revoked_at, Some(earlier));
+            assert!(graph
+                .live_at(Utc::now())
+                .endorsers_of(&id_of(&b))
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn revocation_by_someone_else_does_not_count() {
+        let (a, b, c) = (generate_ed25519(), generate_ed25519(), generate_ed25519());
+        let endorsement: Endorsement = endorse(&a, &[&b]);
+        let mut graph = TrustGraph::from_endorsements([&endorsement]);
+        // Signed by c over a's endorsement, it verifies but isn't a's to make
+        let revocation = revoke(&c, &endorsement, Utc::now() - Duration::hours(1));
+        assert!(graph.add_revocation(&revocation));
+        assert_eq!(graph.live_at(Utc::now()).endorsers_of(&id_of(&b)).len(), 1);
+    }
+
+    // Anchors and claims that break out of a naively quoted DOT string or
+    // XML attribute, and an id whose base64 has + and /
+    fn hostile_graph() -> (TrustGraph, NodeLabels) {
+        let long_id: Id = vec![0xfb; 40];
+        let hostile_key: Key = (
+            KeyType::ED25519,
+            "ssh-ed25519 \"AAAA\"];\n\"x\" -> \"y\" <b>&amp;</b>\\".to_string(),
+        );
+        let mut graph = TrustGraph::new();
+        for (from, to) in [(id(1), id(2)), (id(2), long_id.clone())] {
+            graph.add_edge(TrustEdge {
+                from,
+                to,
+                claims: vec![
+                    (ClaimType::SSHKEY, hostile_key.clone()),
+                    (
+                        ClaimType::SSHKEY,
+                        (KeyType::ED25519, "it's <fine>".to_string()),
+                    ),
+                ],
+                expiration: Utc::now() + Duration::days(365),
+                endorsement: vec![1],
+                revoked_at: None,
+            });
+        }
+        let mut labels: NodeLabels = NodeLabels::new();
+        labels.insert(
+            id(1),
+            vec![
+                (AnchorType::EMAIL, "\"a\"@x.com\n<b>&amp;\\".to_string()),
+                (AnchorType::DNS, "\\\"];\n} digraph evil {".to_string()),
+            ],
+        );
+        labels.insert(
+            long_id,
+            vec![(AnchorType::DNS, "</data></node>&".to_string())],
+        );
+        (graph, labels)
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DotToken {
+        Quoted(String),
+        Symbol(String),
+    }
+
+    // Splits one line of DOT into tokens, quoted strings come back unescaped
+    // the way graphviz reads a label. None if the line doesn't lex, e.g. a
+    // string that is never closed.
+    fn dot_tokens(line: &str) -> Option<Vec<DotToken>> {
+        let mut tokens: Vec<DotToken> = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' => {}
+                '"' => {
+                    let mut quoted = String::new();
+                    loop {
+                        match chars.next()? {
+                            '"' => break,
+                            '\\' => match chars.next()? {
+                                'n' => quoted.push('\n'),
+                                escaped => quoted.push(escaped),
+                            },
+                            c => quoted.push(c),
+                        }
+                    }
+                    tokens.push(DotToken::Quoted(quoted));
+                }
+                '-' if chars.next()? == '>' => tokens.push(DotToken::Symbol("->".to_string())),
+                '[' | ']' | '=' | ',' | ';' | '{' | '}' => {
+                    tokens.push(DotToken::Symbol(c.to_string()))
+                }
+                c if c.is_ascii_alphanumeric() => {
+                    let mut word = String::from(c);
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                        word.push(c);
+                    }
+                    tokens.push(DotToken::Symbol(word));
+                }
+                _ => return None,
+            }
+        }
+        Some(tokens)
+    }
+
+    // From, to, label and tooltip
+    type DotEdge = (String, String, String, String);
+
+    // Node labels by id and every edge, panics if any statement isn't the
+    // shape to_dot writes
+    fn parse_dot(dot: &str) -> (HashMap<String, String>, Vec<DotEdge>) {
+        use DotToken::{Quoted, Symbol};
+        let sym = |s: &str| Symbol(s.to_string());
+        let mut lines = dot.lines();
+        assert_eq!(
+            dot_tokens(lines.next().unwrap()).unwrap(),
+            vec![sym("digraph"), sym("graphlog"), sym("{")]
+        );
+        assert_eq!(lines.next(), Some("    node [shape=box];"));
+        let mut nodes: HashMap<String, String> = HashMap::new();
+        let mut edges: Vec<DotEdge> = Vec::new();
+        for line in lines {
+            let tokens: Vec<DotToken> = dot_tokens(line).unwrap_or_else(|| panic!("{line}"));
+            match tokens.as_slice() {
+                [Symbol(close)] if close == "}" => return (nodes, edges),
+                [Quoted(id), open, Symbol(key), eq, Quoted(label), close, end]
+                    if key == "label"
+                        && [open, eq, close, end]
+                            == [&sym("["), &sym("="), &sym("]"), &sym(";")] =>
+                {
+                    nodes.insert(id.clone(), label.clone());
+                }
+                [Quoted(from), arrow, Quoted(to), open, Symbol(label_key), eq, Quoted(label), comma, Symbol(tooltip_key), eq2, Quoted(tooltip), close, end]
+                    if label_key == "label"
+                        && tooltip_key == "tooltip"
+                        && [arrow, open, eq, comma, eq2, close, end]
+                            == [
+                                &sym("->"),
+                                &sym("["),
+                                &sym("="),
+                                &sym(","),
+                                &sym("="),
+                                &sym("]"),
+                                &sym(";"),
+                            ] =>
+                {
+                    edges.push((from.clone(), to.clone(), label.clone(), tooltip.clone()));
+                }
+                _ => panic!("Unexpected DOT statement: {line}"),
+            }
+        }
+        panic!("DOT graph is never closed");
+    }
+
+    fn xml_unescape(val: &str) -> Option<String> {
+        let mut out = String::new();
+        let mut rest: &str = val;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let semi: usize = rest[amp..].find(';')? + amp;
+            out.push(match &rest[amp + 1..semi] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => return None,
+            });
+            rest = &rest[semi + 1..];
+        }
+        out.push_str(rest);
+        Some(out)
+    }
+
+    struct XmlElement {
+        name: String,
+        attrs: Vec<(String, String)>,
+        text: String,
+    }
+
+    // Every element in a document in the order they open. None if it isn't
+    // well formed XML, as far as to_graphml's output goes: no comments,
+    // CDATA or entities beyond the predefined five.
+    fn parse_xml(xml: &str) -> Option<Vec<XmlElement>> {
+        let mut rest: &str = xml.strip_prefix("<?xml")?;
+        rest = &rest[rest.find("?>")? + 2..];
+        let mut elements: Vec<XmlElement> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        while let Some(lt) = rest.find('<') {
+            let text: String = xml_unescape(&rest[..lt])?;
+            match open.last() {
+                Some(i) => elements[*i].text.push_str(&text),
+                None if !text.trim().is_empty() => return None,
+                None => {}
+            }
+            rest = &rest[lt + 1..];
+            if let Some(closing) = rest.strip_prefix('/') {
+                let gt: usize = closing.find('>')?;
+                if elements[open.pop()?].name != closing[..gt] {
+                    return None;
+                }
+                rest = &closing[gt + 1..];
+                continue;
+            }
+            let name_end: usize = rest.find([' ', '>', '/'])?;
+            let name: String = rest[..name_end].to_string();
+            rest = &rest[name_end..];
+            let mut attrs: Vec<(String, String)> = Vec::new();
+            loop {
+                rest = rest.trim_start();
+                if let Some(after) = rest.strip_prefix("/>") {
+                    elements.push(XmlElement {
+                        name,
+                        attrs,
+                        text: String::new(),
+                    });
+                    rest = after;
+                    break;
+                }
+                if let Some(after) = rest.strip_prefix('>') {
+                    open.push(elements.len());
+                    elements.push(XmlElement {
+                        name,
+                        attrs,
+                        text: String::new(),
+                    });
+                    rest = after;
+                    break;
+                }
+                let eq: usize = rest.find("=\"")?;
+                let attr: &str = &rest[..eq];
+                if !attr.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+                    return None;
+                }
+                let val_end: usize = rest[eq + 2..].find('"')? + eq + 2;
+                let val: &str = &rest[eq + 2..val_end];
+                if val.contains('<') {
+                    return None;
+                }
+                attrs.push((attr.to_string(), xml_unescape(val)?));
+                rest = &rest[val_end + 1..];
+            }
+        }
+        (open.is_empty() && rest.trim().is_empty()).then_some(elements)
+    }
+
+    fn anchors_text(labels: &NodeLabels, id: &Id) -> Vec<String> {
+        labels
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|(anchor_type, anchor)| format!("{anchor_type:?}: {anchor}"))
+            .collect()
+    }
+
+    #[test]
+    fn dot_export_escapes_hostile_labels() {
+        let (graph, labels) = hostile_graph();
+        let (nodes, edges) = parse_dot(&graph.to_dot(&labels));
+        assert_eq!(nodes.len(), 3);
+        for id in graph.nodes() {
+            let mut expected: Vec<String> = vec![encode_block(&id)];
+            expected.extend(anchors_text(&labels, &id));
+            assert_eq!(nodes[&encode_block(&id)], expected.join("\n"));
+        }
+        assert_eq!(edges.len(), 2);
+        for (from, to, label, tooltip) in edges {
+            let edge: &TrustEdge = graph
+                .edges()
+                .find(|edge| encode_block(&edge.from) == from && encode_block(&edge.to) == to)
+                .unwrap();
+            assert!(label.starts_with("SSHKEY, SSHKEY\nexpires "), "{label}");
+            assert_eq!(tooltip, claims_text(&edge.claims));
+        }
+    }
+
+    #[test]
+    fn graphml_export_escapes_hostile_labels() {
+        let (graph, labels) = hostile_graph();
+        let xml: String = graph.to_graphml(&labels);
+        let elements = parse_xml(&xml).unwrap_or_else(|| panic!("Malformed GraphML:\n{xml}"));
+        let data = |key: &str| -> Vec<String> {
+            elements
+                .iter()
+                .filter(|element| {
+                    element.name == "data"
+                        && element.attrs[0] == ("key".to_string(), key.to_string())
+                })
+                .map(|element| element.text.clone())
+                .collect()
+        };
+
+        let node_ids: Vec<String> = elements
+            .iter()
+            .filter(|element| element.name == "node")
+            .map(|element| element.attrs[0].1.clone())
+            .collect();
+        let expected_ids: Vec<String> = graph
+            .sorted_nodes()
+            .iter()
+            .map(|id| encode_block(id))
+            .collect();
+        assert_eq!(node_ids, expected_ids);
+        let expected_anchors: Vec<String> = graph
+            .sorted_nodes()
+            .iter()
+            .map(|id| anchors_text(&labels, id).join("\n"))
+            .collect();
+        assert_eq!(data("anchors"), expected_anchors);
+        let expected_claims: Vec<String> = graph
+            .sorted_edges()
+            .iter()
+            .map(|edge| claims_text(&edge.claims))
+            .collect();
+        assert_eq!(data("claims"), expected_claims);
+    }
+
+    #[test]
+    fn export_checks_catch_unescaped_labels() {
+        // The parsers above are only worth something if they reject what
+        // the escaping is there to prevent
+        assert!(
+            dot_tokens("    \"a\" [label=\"say \"hi\"\"];").is_none_or(|tokens| tokens.len() != 7)
+        );
+        assert!(dot_tokens("    \"a\" [label=\"line").is_none());
+        assert!(parse_xml("<?xml?><a b=\"<\"/>").is_none());
+        assert!(parse_xml("<?xml?><a>&nbsp;</a>").is_none());
+        assert!(parse_xml("<?xml?><a><b></a></b>").is_none());
+        assert!(parse_xml("<?xml?><a>x</a>").is_some());
+    }
+}
@@ -1,74 +1,433 @@
-use std::io::{BufRead, BufReader, Write};
-use std::{fs::File, path::PathBuf};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, Private};
+use tokio::sync::{oneshot, watch, Notify};
 
 use crate::types::common::Decodable;
 
-use super::common::Encodable;
+use super::common::{Encodable, Id, Indexable};
+use super::merkle::{Hash, MerkleTree, SignedTreeHead};
+use super::segment::SegmentedStorage;
+use super::storage::{
+    record_leaf_hash, FileStorage, FsyncPolicy, MemoryStorage, RecoveryReport, Storage,
+};
 
 pub struct Log<T> {
     _log: Vec<T>,
-    path: Option<PathBuf>,
-    file_pos: usize,
-    /* add other metadata below(merkel tree, head) */
+    records: Vec<String>, // each entry as it was stored, what its merkle leaf is hashed over
+    writer: LogWriter<T>,
+    queue: Vec<(T, oneshot::Sender<io::Result<()>>)>, // waiting for the next group commit
+    in_flight: Vec<T>, // copy of the batch being written, neither queued nor in the log
+    queued_signal: Arc<Notify>,
+    index: HashMap<Id, Vec<usize>>, // positions of the entries filed under each id, oldest first
+    terms: HashMap<String, Vec<usize>>, // same for search terms
+    committed: watch::Sender<usize>, // length of the log, for subscribers
+    tree: MerkleTree,
+}
+
+// Writes entries to storage and fsyncs them according to the policy. It is
+// shared between a Log and its group committer so disk I/O happens without
+// holding the lock on the Log.
+pub struct LogWriter<T> {
+    inner: Arc<Mutex<WriterState<T>>>,
+}
+
+struct WriterState<T> {
+    storage: Box<dyn Storage<T> + Send>,
+    fsync: FsyncPolicy,
+    dirty: bool, // appended to storage since the last fsync
+}
+
+// Entries taken off the queue to be written together, along with whoever is
+// waiting on them
+pub struct Batch<T> {
+    entries: Vec<T>,
+    waiting: Vec<oneshot::Sender<io::Result<()>>>,
+}
+
+impl<T> Batch<T> {
+    pub fn entries(&self) -> &[T] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T> Clone for LogWriter<T> {
+    fn clone(&self) -> Self {
+        LogWriter {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> LogWriter<T> {
+    fn new(storage: Box<dyn Storage<T> + Send>) -> LogWriter<T> {
+        LogWriter {
+            inner: Arc::new(Mutex::new(WriterState {
+                storage,
+                fsync: FsyncPolicy::default(),
+                dirty: false,
+            })),
+        }
+    }
+
+    // Writes vals in order and returns how many of them are in storage,
+    // which is all of them unless there is an error. Under the append policy
+    // every entry is fsynced on its own so a failure part way keeps the
+    // entries before it.
+    pub fn write(&self, vals: &[T]) -> (usize, io::Result<()>) {
+        let mut state = self.inner.lock().unwrap();
+        match state.fsync {
+            FsyncPolicy::Append => {
+                for (written, val) in vals.iter().enumerate() {
+                    if let Err(why) = state.storage.append(std::slice::from_ref(val)) {
+                        return (written, Err(why));
+                    }
+                    state.dirty = true;
+                    state.sync();
+                }
+            }
+            FsyncPolicy::Batch => {
+                if let Err(why) = state.storage.append(vals) {
+                    return (0, Err(why));
+                }
+                state.dirty = true;
+                state.sync();
+            }
+            FsyncPolicy::Periodic { .. } => {
+                if let Err(why) = state.storage.append(vals) {
+                    return (0, Err(why));
+                }
+                state.dirty = true;
+            }
+        }
+        (vals.len(), Ok(()))
+    }
+
+    // Fsyncs anything written since the last sync
+    pub fn sync(&self) {
+        self.inner.lock().unwrap().sync();
+    }
+}
+
+impl<T> WriterState<T> {
+    // A failed fsync leaves no way to tell what reached the disk so it is
+    // fatal. The whole process goes down rather than panicking, which would
+    // only take the committer with it and leave appends waiting forever.
+    fn sync(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Err(why) = self.storage.sync() {
+            println!("Error syncing log storage, aborting: {why}");
+            std::process::abort();
+        }
+        self.dirty = false;
+    }
 }
 
 impl<T> Log<T> {
+    // Persists to a file at path, or only keeps the log in memory if None
     pub fn new(path: Option<String>) -> Log<T>
     where
-        T: Encodable,
+        T: Encodable + Indexable + Decodable<T> + Clone + Send + 'static,
     {
-        let _log: Vec<T> = Vec::new();
-        Log {
-            _log,
-            path: path.map(PathBuf::from),
-            file_pos: 0,
+        match path {
+            Some(path) => Log::new_from_file(path),
+            None => Log::with_storage(Box::new(MemoryStorage::new())),
         }
     }
 
     pub fn new_from_file(path: String) -> Log<T>
     where
-        T: Decodable<T>,
+        T: Encodable + Indexable + Decodable<T> + Send + 'static,
     {
-        let file: File = match File::open(&path) {
-            Err(why) => {
-                println!("Couldn't open file: {why}");
-                return Log {
-                    _log: Vec::new(),
-                    path: None,
-                    file_pos: 0,
-                };
-            }
-            Ok(file) => file,
-        };
-        let mut log: Vec<T> = Vec::new();
-        let buf_reader = BufReader::new(file);
+        match FileStorage::open(path) {
+            Err(why) => panic!("Couldn't open log file: {why}"),
+            Ok(storage) => Log::with_storage(Box::new(storage)),
+        }
+    }
 
-        for entry_b64 in buf_reader.lines() {
-            if let Err(why) = entry_b64 {
-                println!("Error reading line from file: {why}");
-                continue;
-            }
-            let entry: T = match T::decode(&entry_b64.unwrap()) {
-                Some(entry) => entry,
-                None => panic!("Failed to read entry; log corrupted"),
-            };
-            log.push(entry);
+    // Persists to a directory of binary segments, see segment.rs
+    pub fn new_segmented(dir: String, segment_bytes: u64) -> Log<T>
+    where
+        T: Encodable + Indexable + Decodable<T> + Send + 'static,
+    {
+        match SegmentedStorage::open(dir, segment_bytes) {
+            Err(why) => panic!("Couldn't open log segments: {why}"),
+            Ok(storage) => Log::with_storage(Box::new(storage)),
         }
+    }
 
-        let file_pos: usize = log.len();
+    // Loads every entry already in storage. Leaves are hashed over the
+    // stored records rather than the decoded entries so an entry written by
+    // an older version keeps the leaf it was logged under.
+    pub fn with_storage(storage: Box<dyn Storage<T> + Send>) -> Log<T>
+    where
+        T: Encodable + Indexable + Decodable<T>,
+    {
+        let records: Vec<String> = match storage.read_records(0, storage.len()) {
+            Err(why) => panic!("Failed to read entries from storage: {why}"),
+            Ok(records) => records,
+        };
+        let mut log: Vec<T> = Vec::with_capacity(records.len());
+        for (pos, record) in records.iter().enumerate() {
+            match T::decode(record) {
+                None => panic!("Failed to read entry {pos}; log corrupted"),
+                Some(entry) => log.push(entry),
+            }
+        }
+        let tree: MerkleTree = match storage.leaf_hashes() {
+            Some(leaves) if leaves.len() == records.len() => MerkleTree::from_leaf_hashes(leaves),
+            _ => MerkleTree::from_leaf_hashes(
+                records
+                    .iter()
+                    .map(|record| record_leaf_hash(record))
+                    .collect(),
+            ),
+        };
+        let log_len: usize = log.len();
+        let mut index: HashMap<Id, Vec<usize>> = HashMap::new();
+        let mut terms: HashMap<String, Vec<usize>> = HashMap::new();
+        for (pos, entry) in log.iter().enumerate() {
+            Log::index_entry(&mut index, &mut terms, pos, entry);
+        }
         Log {
             _log: log,
-            path: Some(PathBuf::from(&path)),
-            file_pos,
+            records,
+            writer: LogWriter::new(storage),
+            queue: Vec::new(),
+            in_flight: Vec::new(),
+            queued_signal: Arc::new(Notify::new()),
+            index,
+            terms,
+            committed: watch::Sender::new(log_len),
+            tree,
+        }
+    }
+
+    pub fn set_fsync_policy(&mut self, fsync: FsyncPolicy) {
+        self.writer.inner.lock().unwrap().fsync = fsync;
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.writer.inner.lock().unwrap().fsync
+    }
+
+    // What had to be repaired when the log was opened
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.writer.inner.lock().unwrap().storage.recovery_report()
+    }
+
+    // Handle for writing to this log's storage without holding the log
+    pub fn writer(&self) -> LogWriter<T> {
+        self.writer.clone()
+    }
+
+    // Writes val straight to storage. The entry is only added to the log
+    // once storage has it, so an Ok means it will be there after a restart
+    // (within the fsync interval for the periodic policy). This doesn't go
+    // through the queue, so it must not be mixed with a group committer.
+    pub fn append(&mut self, val: T) -> io::Result<()>
+    where
+        T: Encodable + Indexable,
+    {
+        let (_, result) = self.writer.write(std::slice::from_ref(&val));
+        result?;
+        self.push(val);
+        Ok(())
+    }
+
+    // Queues val for the next group commit, see utils/group_commit.rs. The
+    // receiver gets the result once the batch holding val has been written,
+    // val is only in the log after that.
+    pub fn queue(&mut self, val: T) -> oneshot::Receiver<io::Result<()>> {
+        let (done, committed) = oneshot::channel();
+        self.queue.push((val, done));
+        self.queued_signal.notify_one();
+        committed
+    }
+
+    // Entries waiting to be committed, oldest first, including the batch
+    // that is being written. Checks for duplicates have to look at these as
+    // well as the log.
+    pub fn queued(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.in_flight
+            .iter()
+            .chain(self.queue.iter().map(|(val, _)| val))
+    }
+
+    pub fn queued_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    // Notified whenever an entry is queued
+    pub fn queued_signal(&self) -> Arc<Notify> {
+        self.queued_signal.clone()
+    }
+
+    // Takes up to max of the oldest queued entries, they still show up in
+    // queued until the batch is committed
+    pub fn take_batch(&mut self, max: usize) -> Batch<T>
+    where
+        T: Clone,
+    {
+        let taken: Vec<(T, oneshot::Sender<io::Result<()>>)> =
+            self.queue.drain(..max.min(self.queue.len())).collect();
+        let (entries, waiting): (Vec<T>, Vec<_>) = taken.into_iter().unzip();
+        self.in_flight = entries.clone();
+        Batch { entries, waiting }
+    }
+
+    // Adds the first `written` entries of a batch that went through
+    // LogWriter::write to the log and lets everyone waiting on the batch
+    // know how it went. Batches have to be committed in the order they were
+    // taken.
+    pub fn commit_batch(&mut self, batch: Batch<T>, written: usize, result: io::Result<()>)
+    where
+        T: Encodable + Indexable,
+    {
+        self.in_flight.clear();
+        let failure: Option<(io::ErrorKind, String)> =
+            result.err().map(|why| (why.kind(), why.to_string()));
+        for (i, (val, done)) in batch.entries.into_iter().zip(batch.waiting).enumerate() {
+            if i < written {
+                self.push(val);
+                let _ = done.send(Ok(()));
+            } else if let Some((kind, why)) = &failure {
+                let _ = done.send(Err(io::Error::new(*kind, why.clone())));
+            }
         }
     }
 
-    pub fn append(&mut self, val: T)
+    fn push(&mut self, val: T)
     where
-        T: Encodable,
+        T: Encodable + Indexable,
     {
+        let record: String = val.encode();
+        self.tree.push_leaf_hash(record_leaf_hash(&record));
+        Log::index_entry(&mut self.index, &mut self.terms, self._log.len(), &val);
         self._log.push(val);
-        self.persist(); // TODO THIS IS TEMPORARY IN FUTURE PERSIST ON BATCH;
+        self.records.push(record);
+        self.committed.send_replace(self._log.len());
+    }
+
+    // Sees the length of the log change every time entries are added
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.committed.subscribe()
+    }
+
+    fn index_entry(
+        index: &mut HashMap<Id, Vec<usize>>,
+        terms: &mut HashMap<String, Vec<usize>>,
+        pos: usize,
+        entry: &T,
+    ) where
+        T: Indexable,
+    {
+        let mut ids: Vec<Id> = entry.index_ids();
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            index.entry(id).or_default().push(pos);
+        }
+        let mut entry_terms: Vec<String> = entry.search_terms();
+        entry_terms.sort();
+        entry_terms.dedup();
+        for term in entry_terms {
+            terms.entry(term).or_default().push(pos);
+        }
+    }
+
+    // Positions of every entry filed under id, oldest first
+    pub fn positions(&self, id: &Id) -> &[usize] {
+        self.index
+            .get(id)
+            .map_or(&[], |positions| positions.as_slice())
+    }
+
+    // Positions of every entry with the search term, oldest first. Older
+    // entries stay in here after a newer one for the same id drops the term.
+    pub fn term_positions(&self, term: &str) -> &[usize] {
+        self.terms
+            .get(term)
+            .map_or(&[], |positions| positions.as_slice())
+    }
+
+    // Most recent entry filed under id
+    pub fn latest(&self, id: &Id) -> Option<&T> {
+        self.latest_position(id).map(|pos| &self._log[pos])
+    }
+
+    pub fn latest_position(&self, id: &Id) -> Option<usize> {
+        self.positions(id).last().copied()
+    }
+
+    // Every entry filed under id with its position, oldest first
+    pub fn history(&self, id: &Id) -> impl DoubleEndedIterator<Item = (usize, &T)> {
+        self.positions(id)
+            .iter()
+            .map(|pos| (*pos, &self._log[*pos]))
+    }
+
+    // Same as history but each entry as it was stored
+    pub fn history_records(&self, id: &Id) -> impl DoubleEndedIterator<Item = (usize, &str)> {
+        self.positions(id)
+            .iter()
+            .map(|pos| (*pos, self.records[*pos].as_str()))
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        self.tree.root()
+    }
+
+    pub fn tree_head(
+        &self,
+        prv_key: &PKey<Private>,
+    ) -> std::result::Result<SignedTreeHead, ErrorStack> {
+        SignedTreeHead::new(
+            self.tree.len() as u64,
+            self.tree.root(),
+            Utc::now(),
+            prv_key,
+        )
+    }
+
+    // Signed head for the tree made of the first `tree_size` entries,
+    // None if the log is not that large yet
+    pub fn tree_head_at(
+        &self,
+        tree_size: usize,
+        prv_key: &PKey<Private>,
+    ) -> Option<std::result::Result<SignedTreeHead, ErrorStack>> {
+        let root: Hash = self.tree.root_at(tree_size)?;
+        Some(SignedTreeHead::new(
+            tree_size as u64,
+            root,
+            Utc::now(),
+            prv_key,
+        ))
+    }
+
+    // Audit path proving the entry at `index` is in the tree of size `tree_size`
+    pub fn inclusion_proof(&self, index: usize, tree_size: usize) -> Option<Vec<Hash>> {
+        self.tree.inclusion_proof(index, tree_size)
+    }
+
+    // Proof that the tree of size `old_size` is a prefix of the current tree
+    pub fn consistency_proof(&self, old_size: usize) -> Option<Vec<Hash>> {
+        self.tree.consistency_proof(old_size, self.tree.len())
     }
 
     pub fn head(&mut self) -> Option<&T> {
@@ -88,6 +447,33 @@ impl<T> Log<T> {
         self._log[start..].to_vec()
     }
 
+    // Up to limit entries starting at start, clamped to the entries that exist
+    pub fn range(&self, start: usize, limit: usize) -> &[T] {
+        let start: usize = start.min(self._log.len());
+        let end: usize = start.saturating_add(limit).min(self._log.len());
+        &self._log[start..end]
+    }
+
+    // Same as range but the entries as they were stored, see record
+    pub fn record_range(&self, start: usize, limit: usize) -> &[String] {
+        let start: usize = start.min(self.records.len());
+        let end: usize = start.saturating_add(limit).min(self.records.len());
+        &self.records[start..end]
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self._log.get(index)
+    }
+
+    // The entry at index as it was stored, proofs are over these bytes
+    pub fn record(&self, index: usize) -> Option<&str> {
+        self.records.get(index).map(|record| record.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self._log.iter()
+    }
+
     pub fn len(&self) -> usize {
         self._log.len()
     }
@@ -105,30 +491,284 @@ impl<T> Log<T> {
         self._log.iter().rev().find(|x| predicate(*x))
     }
 
-    pub fn persist(&mut self)
+    // Same as search but returns the index of the most recent match
+    pub fn position<P>(&self, predicate: P) -> Option<usize>
     where
-        T: Encodable,
+        P: FnMut(&T) -> bool,
     {
-        if self.file_pos == self._log.len() {
-            println!("No new records");
-            return; // early return if we haven't had new entries
-        }
-        if let Some(path) = &self.path {
-            let mut file: File = match File::options().append(true).create(true).open(path) {
-                Err(why) => {
-                    println!("Error opening file to persist log to: {why}");
-                    return;
-                }
-                Ok(file) => file,
-            };
-            for idx in self.file_pos..self._log.len() {
-                let entry: &T = self._log.get(idx).unwrap();
-                let entry_b64 = entry.encode();
-                writeln!(file, "{entry_b64}").unwrap(); // TODO should not just unwrap this
+        self._log.iter().rposition(predicate)
+    }
+
+    // Fsyncs anything appended since the last sync
+    pub fn sync(&mut self) {
+        self.writer.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Entry filed under one id with an optional search term, encoded as
+    // "id:term:n"
+    #[derive(Clone, Debug, PartialEq)]
+    struct Entry {
+        id: u8,
+        term: Option<String>,
+        n: u32,
+    }
+
+    fn entry(id: u8, term: Option<&str>, n: u32) -> Entry {
+        Entry {
+            id,
+            term: term.map(|term| term.to_string()),
+            n,
+        }
+    }
+
+    fn id(n: u8) -> Id {
+        vec![n; 32]
+    }
+
+    impl Encodable for Entry {
+        fn encode(&self) -> String {
+            format!(
+                "{}:{}:{}",
+                self.id,
+                self.term.as_deref().unwrap_or(""),
+                self.n
+            )
+        }
+    }
+
+    impl Decodable<Entry> for Entry {
+        fn decode(record: &str) -> Option<Entry> {
+            let mut parts = record.split(':');
+            let id: u8 = parts.next()?.parse().ok()?;
+            let term: Option<&str> = Some(parts.next()?).filter(|term| !term.is_empty());
+            let n: u32 = parts.next()?.parse().ok()?;
+            Some(entry(id, term, n))
+        }
+    }
+
+    impl Indexable for Entry {
+        fn index_ids(&self) -> Vec<Id> {
+            vec![id(self.id)]
+        }
+
+        fn search_terms(&self) -> Vec<String> {
+            self.term.iter().cloned().collect()
+        }
+    }
+
+    // id 1 at 0, 2 and 4, id 2 at 1 and 3. Id 1 drops its term at 4.
+    fn five_entries() -> Vec<Entry> {
+        vec![
+            entry(1, Some("a"), 0),
+            entry(2, Some("b"), 1),
+            entry(1, Some("a"), 2),
+            entry(2, None, 3),
+            entry(1, None, 4),
+        ]
+    }
+
+    fn memory_log(entries: &[Entry]) -> Log<Entry> {
+        let mut log: Log<Entry> = Log::new(None);
+        for entry in entries {
+            log.append(entry.clone()).unwrap();
+        }
+        log
+    }
+
+    // Memory storage that refuses any write holding an entry numbered
+    // FAILING_N
+    struct FailingStorage(MemoryStorage<Entry>);
+
+    const FAILING_N: u32 = 99;
+
+    impl Storage<Entry> for FailingStorage {
+        fn append(&mut self, entries: &[Entry]) -> io::Result<()> {
+            if entries.iter().any(|entry| entry.n == FAILING_N) {
+                return Err(io::Error::other("disk full"));
             }
-            self.file_pos = self._log.len();
-        } else {
-            println!("No path configured to persist log to");
+            self.0.append(entries)
+        }
+
+        fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+            self.0.read_records(start, end)
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn failing_log() -> Log<Entry> {
+        let mut log: Log<Entry> = Log::with_storage(Box::new(FailingStorage(MemoryStorage::new())));
+        for entry in &five_entries()[..2] {
+            log.append(entry.clone()).unwrap();
+        }
+        log
+    }
+
+    // Queues three entries for id 3 with the failing one in the middle
+    fn queue_with_failure(log: &mut Log<Entry>) -> Vec<oneshot::Receiver<io::Result<()>>> {
+        [10, FAILING_N, 11]
+            .into_iter()
+            .map(|n| log.queue(entry(3, Some("c"), n)))
+            .collect()
+    }
+
+    fn leaves(entries: &[Entry]) -> Vec<Hash> {
+        entries
+            .iter()
+            .map(|entry| record_leaf_hash(&entry.encode()))
+            .collect()
+    }
+
+    #[test]
+    fn append_adds_entries_records_and_leaves() {
+        let entries: Vec<Entry> = five_entries();
+        let mut log: Log<Entry> = Log::new(None);
+        let committed = log.subscribe();
+        assert!(log.is_empty());
+        for (i, entry) in entries.iter().enumerate() {
+            log.append(entry.clone()).unwrap();
+            assert_eq!(log.len(), i + 1);
+            assert_eq!(*committed.borrow(), i + 1);
+        }
+        assert_eq!(log.get(2), Some(&entries[2]));
+        assert_eq!(log.record(2), Some("1:a:2"));
+        assert_eq!(log.get(5), None);
+        assert_eq!(log.head(), Some(&entries[0]));
+        assert_eq!(log.tail(), Some(&entries[4]));
+        assert_eq!(
+            log.root_hash(),
+            MerkleTree::from_leaf_hashes(leaves(&entries)).root()
+        );
+    }
+
+    #[test]
+    fn range_is_clamped_to_the_log() {
+        let entries: Vec<Entry> = five_entries();
+        let log: Log<Entry> = memory_log(&entries);
+        assert_eq!(log.range(1, 2), &entries[1..3]);
+        assert_eq!(log.range(3, 10), &entries[3..]);
+        assert!(log.range(5, 1).is_empty());
+        assert!(log.range(9, usize::MAX).is_empty());
+        assert_eq!(log.record_range(3, 10), vec!["2::3", "1::4"]);
+        assert!(log.record_range(usize::MAX, usize::MAX).is_empty());
+        assert_eq!(log.tailn(2), entries[3..].to_vec());
+        assert_eq!(log.tailn(10), entries);
+    }
+
+    #[test]
+    fn index_lookups() {
+        let entries: Vec<Entry> = five_entries();
+        let log: Log<Entry> = memory_log(&entries);
+        assert_eq!(log.positions(&id(1)), &[0, 2, 4]);
+        assert_eq!(log.positions(&id(2)), &[1, 3]);
+        assert!(log.positions(&id(3)).is_empty());
+
+        assert_eq!(log.latest(&id(1)), Some(&entries[4]));
+        assert_eq!(log.latest_position(&id(2)), Some(3));
+        assert_eq!(log.latest(&id(3)), None);
+
+        // Terms keep the older entries that had them
+        assert_eq!(log.term_positions("a"), &[0, 2]);
+        assert_eq!(log.term_positions("b"), &[1]);
+        assert!(log.term_positions("c").is_empty());
+
+        let history: Vec<(usize, &Entry)> = log.history(&id(2)).collect();
+        assert_eq!(history, vec![(1, &entries[1]), (3, &entries[3])]);
+        let records: Vec<(usize, &str)> = log.history_records(&id(2)).rev().collect();
+        assert_eq!(records, vec![(3, "2::3"), (1, "2:b:1")]);
+    }
+
+    #[test]
+    fn reopening_storage_rebuilds_the_index_and_tree() {
+        let entries: Vec<Entry> = five_entries();
+        let mut storage: MemoryStorage<Entry> = MemoryStorage::new();
+        storage.append(&entries).unwrap();
+        let log: Log<Entry> = Log::with_storage(Box::new(storage));
+        let appended: Log<Entry> = memory_log(&entries);
+        assert_eq!(log.len(), 5);
+        assert_eq!(log.root_hash(), appended.root_hash());
+        assert_eq!(log.positions(&id(1)), appended.positions(&id(1)));
+        assert_eq!(log.term_positions("a"), appended.term_positions("a"));
+        assert_eq!(log.record_range(0, 5), appended.record_range(0, 5));
+    }
+
+    #[test]
+    fn batch_stays_queued_until_committed() {
+        let mut log: Log<Entry> = Log::new(None);
+        let mut committed: Vec<oneshot::Receiver<io::Result<()>>> = five_entries()[..3]
+            .iter()
+            .map(|entry| log.queue(entry.clone()))
+            .collect();
+        let batch: Batch<Entry> = log.take_batch(2);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(log.queued_len(), 1);
+        // Still visible to duplicate checks while it is being written
+        let queued: Vec<u32> = log.queued().map(|entry| entry.n).collect();
+        assert_eq!(queued, vec![0, 1, 2]);
+        assert!(log.is_empty());
+
+        let (written, result) = log.writer().write(batch.entries());
+        log.commit_batch(batch, written, result);
+        assert_eq!(log.len(), 2);
+        let queued: Vec<u32> = log.queued().map(|entry| entry.n).collect();
+        assert_eq!(queued, vec![2]);
+        assert!(committed[0].try_recv().unwrap().is_ok());
+        assert!(committed[1].try_recv().unwrap().is_ok());
+        assert!(committed[2].try_recv().is_err());
+    }
+
+    #[test]
+    fn failed_batch_fails_every_waiter_and_leaves_the_log() {
+        let mut log: Log<Entry> = failing_log();
+        let root: Hash = log.root_hash();
+        let mut committed = queue_with_failure(&mut log);
+        let batch: Batch<Entry> = log.take_batch(3);
+        let (written, result) = log.writer().write(batch.entries());
+        assert_eq!(written, 0);
+        log.commit_batch(batch, written, result);
+
+        for done in &mut committed {
+            let why: io::Error = done.try_recv().unwrap().unwrap_err();
+            assert_eq!(why.to_string(), "disk full");
         }
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.root_hash(), root);
+        assert!(log.positions(&id(3)).is_empty());
+        assert!(log.term_positions("c").is_empty());
+        assert_eq!(log.queued().count(), 0);
+    }
+
+    #[test]
+    fn append_policy_keeps_entries_written_before_a_failure() {
+        let mut log: Log<Entry> = failing_log();
+        log.set_fsync_policy(FsyncPolicy::Append);
+        let mut committed = queue_with_failure(&mut log);
+        let batch: Batch<Entry> = log.take_batch(3);
+        let (written, result) = log.writer().write(batch.entries());
+        assert_eq!(written, 1);
+        log.commit_batch(batch, written, result);
+
+        assert!(committed[0].try_recv().unwrap().is_ok());
+        assert!(committed[1].try_recv().unwrap().is_err());
+        assert!(committed[2].try_recv().unwrap().is_err());
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.positions(&id(3)), &[2]);
+        let mut entries: Vec<Entry> = five_entries()[..2].to_vec();
+        entries.push(entry(3, Some("c"), 10));
+        assert_eq!(
+            log.root_hash(),
+            MerkleTree::from_leaf_hashes(leaves(&entries)).root()
+        );
     }
 }
@@ -0,0 +1,173 @@
+use super::canonical::{CanonicalEncoder, LOG_KEY_ROTATION_DOMAIN};
+use super::common::Sig;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use openssl::error::ErrorStack;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+// Hands the log's signing role from old_key to new_key. It is signed by the
+// old key so anyone who trusted the log under it can follow the log to the
+// new key, and tree heads signed before rotated_at still verify under the
+// old one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogKeyRotation {
+    old_key: Vec<u8>, // raw Ed25519 public keys
+    new_key: Vec<u8>,
+    #[serde(with = "ts_seconds")]
+    rotated_at: DateTime<Utc>,
+    sig: Sig,
+}
+
+impl LogKeyRotation {
+    pub fn new(
+        old_prv_key: &PKey<Private>,
+        new_key: &PKey<Public>,
+        rotated_at: DateTime<Utc>,
+    ) -> std::result::Result<LogKeyRotation, ErrorStack> {
+        let old_key: Vec<u8> = old_prv_key.raw_public_key()?;
+        let new_key: Vec<u8> = new_key.raw_public_key()?;
+        let data: Vec<u8> = LogKeyRotation::args_to_signable(&old_key, &new_key, rotated_at);
+        let mut signer = Signer::new_without_digest(old_prv_key)?;
+        let sig: Sig = signer.sign_oneshot_to_vec(&data)?;
+        Ok(LogKeyRotation {
+            old_key,
+            new_key,
+            rotated_at,
+            sig,
+        })
+    }
+
+    pub fn get_old_key(&self) -> &[u8] {
+        &self.old_key
+    }
+
+    pub fn get_new_key(&self) -> &[u8] {
+        &self.new_key
+    }
+
+    pub fn get_rotated_at(&self) -> DateTime<Utc> {
+        self.rotated_at
+    }
+
+    // Checked against the embedded old key, whether that key is one the
+    // caller trusts is up to them
+    pub fn verify(&self) -> bool {
+        let old_key: PKey<Public> =
+            match PKey::public_key_from_raw_bytes(&self.old_key, Id::ED25519) {
+                Err(_) => return false,
+                Ok(old_key) => old_key,
+            };
+        let verified: bool = match Verifier::new_without_digest(&old_key) {
+            Err(_) => false,
+            Ok(mut verify) => verify
+                .verify_oneshot(&self.sig, &self.signable())
+                .unwrap_or(false),
+        };
+        verified
+    }
+
+    // Bytes covered by the signature, see canonical.rs for the byte layout
+    pub fn signable(&self) -> Vec<u8> {
+        LogKeyRotation::args_to_signable(&self.old_key, &self.new_key, self.rotated_at)
+    }
+
+    fn args_to_signable(old_key: &[u8], new_key: &[u8], rotated_at: DateTime<Utc>) -> Vec<u8> {
+        CanonicalEncoder::new(LOG_KEY_ROTATION_DOMAIN)
+            .put_bytes(old_key)
+            .put_bytes(new_key)
+            .put_time(rotated_at)
+            .finish()
+    }
+}
+
+// Whether rotations, oldest first, are each signed by the key before them and
+// end at current_key
+pub fn verify_rotations(rotations: &[LogKeyRotation], current_key: &[u8]) -> bool {
+    let mut expected: Option<&[u8]> = None;
+    for rotation in rotations {
+        if !rotation.verify() || expected.is_some_and(|key| key != rotation.get_old_key()) {
+            return false;
+        }
+        expected = Some(rotation.get_new_key());
+    }
+    expected.is_none_or(|key| key == current_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::keys::generate_ed25519;
+    use chrono::Duration;
+
+    fn keys(count: usize) -> Vec<(PKey<Public>, PKey<Private>)> {
+        (0..count).map(|_| generate_ed25519()).collect()
+    }
+
+    fn raw(key: &(PKey<Public>, PKey<Private>)) -> Vec<u8> {
+        key.0.raw_public_key().unwrap()
+    }
+
+    // Rotations from each key to the next, a day apart
+    fn chain(keys: &[(PKey<Public>, PKey<Private>)]) -> Vec<LogKeyRotation> {
+        let start: DateTime<Utc> = Utc::now() - Duration::days(30);
+        keys.windows(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                LogKeyRotation::new(&pair[0].1, &pair[1].0, start + Duration::days(i as i64))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_chain_ends_at_the_current_key() {
+        let keys = keys(4);
+        let rotations: Vec<LogKeyRotation> = chain(&keys);
+        assert!(rotations.iter().all(|rotation| rotation.verify()));
+        assert!(verify_rotations(&rotations, &raw(&keys[3])));
+        // Any prefix is a valid chain to the key it ends at
+        assert!(verify_rotations(&rotations[..1], &raw(&keys[1])));
+        assert!(verify_rotations(&[], &raw(&keys[0])));
+        // But not to a key it doesn't end at
+        assert!(!verify_rotations(&rotations, &raw(&keys[2])));
+        assert!(!verify_rotations(&rotations[..1], &raw(&keys[3])));
+    }
+
+    #[test]
+    fn broken_link_is_rejected() {
+        let keys = keys(4);
+        let rotations: Vec<LogKeyRotation> = chain(&keys);
+        // 0 -> 1 followed by 2 -> 3, nothing hands 1 over to 2
+        let broken: Vec<LogKeyRotation> = vec![rotations[0].clone(), rotations[2].clone()];
+        assert!(!verify_rotations(&broken, &raw(&keys[3])));
+    }
+
+    #[test]
+    fn reordered_chain_is_rejected() {
+        let keys = keys(4);
+        let mut rotations: Vec<LogKeyRotation> = chain(&keys);
+        rotations.swap(0, 1);
+        assert!(!verify_rotations(&rotations, &raw(&keys[3])));
+        rotations.reverse();
+        assert!(!verify_rotations(&rotations, &raw(&keys[3])));
+    }
+
+    #[test]
+    fn rotation_signed_by_the_wrong_key_is_rejected() {
+        let keys = keys(4);
+        let mut rotations: Vec<LogKeyRotation> = chain(&keys[..3]);
+        // Claims to hand key 1 over to 3 but is signed by 3 itself
+        let mut forged = LogKeyRotation::new(&keys[3].1, &keys[3].0, Utc::now()).unwrap();
+        forged.old_key = raw(&keys[1]);
+        assert!(!forged.verify());
+        rotations[1] = forged;
+        assert!(!verify_rotations(&rotations, &raw(&keys[3])));
+
+        // A genuine rotation whose new key was swapped after signing
+        let mut rotations: Vec<LogKeyRotation> = chain(&keys[..3]);
+        rotations[1].new_key = raw(&keys[3]);
+        assert!(!verify_rotations(&rotations, &raw(&keys[3])));
+    }
+}
@@ -0,0 +1,658 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, Private, Public};
+use openssl::sha::Sha256;
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+
+use super::canonical::{CanonicalEncoder, TREE_HEAD_DOMAIN};
+use super::common::Sig;
+
+pub type Hash = Vec<u8>;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+// RFC 6962 leaf hash: SHA-256(0x00 || data)
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finish().to_vec()
+}
+
+// RFC 6962 interior node hash: SHA-256(0x01 || left || right)
+pub fn node_hash(left: &[u8], right: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finish().to_vec()
+}
+
+pub fn empty_root() -> Hash {
+    Sha256::new().finish().to_vec()
+}
+
+// Largest power of two strictly less than n (n > 1)
+fn split_point(n: usize) -> usize {
+    let mut k: usize = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+// Merkle tree over the leaves of a log as described in RFC 6962.
+// levels[0] holds the leaf hashes, levels[i][j] holds the hash of the
+// complete subtree covering leaves [j * 2^i, (j + 1) * 2^i) so roots and
+// proofs for any tree size can be computed in O(log n) hashes.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new() -> MerkleTree {
+        MerkleTree { levels: Vec::new() }
+    }
+
+    pub fn from_leaf_hashes(leaves: Vec<Hash>) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        for leaf in leaves {
+            tree.push_leaf_hash(leaf);
+        }
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, |leaves| leaves.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        self.levels.first().map_or(&[], |leaves| leaves.as_slice())
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.push_leaf_hash(leaf_hash(data));
+    }
+
+    pub fn push_leaf_hash(&mut self, leaf: Hash) {
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        self.levels[0].push(leaf);
+
+        // Complete every subtree the new leaf closes off
+        let mut level: usize = 0;
+        while self.levels[level].len().is_multiple_of(2) {
+            let nodes: &Vec<Hash> = &self.levels[level];
+            let parent: Hash = node_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level + 1].push(parent);
+            level += 1;
+        }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root_at(self.len()).unwrap()
+    }
+
+    // Root of the tree made of the first `size` leaves
+    pub fn root_at(&self, size: usize) -> Option<Hash> {
+        if size > self.len() {
+            None
+        } else if size == 0 {
+            Some(empty_root())
+        } else {
+            Some(self.subtree_hash(0, size))
+        }
+    }
+
+    // MTH(D[start:end]), end > start
+    fn subtree_hash(&self, start: usize, end: usize) -> Hash {
+        let size: usize = end - start;
+        if size.is_power_of_two() && start.is_multiple_of(size) {
+            let level: usize = size.trailing_zeros() as usize;
+            return self.levels[level][start >> level].clone();
+        }
+        let k: usize = split_point(size);
+        node_hash(
+            &self.subtree_hash(start, start + k),
+            &self.subtree_hash(start + k, end),
+        )
+    }
+
+    // Audit path for leaf `index` in the tree of the first `size` leaves
+    pub fn inclusion_proof(&self, index: usize, size: usize) -> Option<Vec<Hash>> {
+        if index >= size || size > self.len() {
+            return None;
+        }
+        let mut proof: Vec<Hash> = Vec::new();
+        self.path(index, 0, size, &mut proof);
+        Some(proof)
+    }
+
+    fn path(&self, index: usize, start: usize, end: usize, proof: &mut Vec<Hash>) {
+        let size: usize = end - start;
+        if size == 1 {
+            return;
+        }
+        let k: usize = split_point(size);
+        if index < start + k {
+            self.path(index, start, start + k, proof);
+            proof.push(self.subtree_hash(start + k, end));
+        } else {
+            self.path(index, start + k, end, proof);
+            proof.push(self.subtree_hash(start, start + k));
+        }
+    }
+
+    // Consistency proof between the trees of size `old_size` and `new_size`
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Option<Vec<Hash>> {
+        if old_size > new_size || new_size > self.len() {
+            return None;
+        }
+        let mut proof: Vec<Hash> = Vec::new();
+        if old_size > 0 && old_size < new_size {
+            self.subproof(old_size, 0, new_size, true, &mut proof);
+        }
+        Some(proof)
+    }
+
+    fn subproof(&self, m: usize, start: usize, end: usize, complete: bool, proof: &mut Vec<Hash>) {
+        let size: usize = end - start;
+        if m == size {
+            if !complete {
+                proof.push(self.subtree_hash(start, end));
+            }
+            return;
+        }
+        let k: usize = split_point(size);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, proof);
+            proof.push(self.subtree_hash(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, proof);
+            proof.push(self.subtree_hash(start, start + k));
+        }
+    }
+}
+
+// Verify an audit path as described in RFC 9162 section 2.1.3.2
+pub fn verify_inclusion(
+    leaf: &[u8],
+    index: usize,
+    tree_size: usize,
+    proof: &[Hash],
+    root: &[u8],
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let mut fnode: usize = index;
+    let mut snode: usize = tree_size - 1;
+    let mut r: Hash = leaf.to_vec();
+    for p in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            r = node_hash(p, &r);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && r == root
+}
+
+// Verify a consistency proof as described in RFC 9162 section 2.1.4.2
+pub fn verify_consistency(
+    old_size: usize,
+    new_size: usize,
+    old_root: &[u8],
+    new_root: &[u8],
+    proof: &[Hash],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+    if old_size == 0 {
+        // Every tree is an extension of the empty tree
+        return proof.is_empty() && old_root == empty_root().as_slice();
+    }
+    if proof.is_empty() {
+        return false;
+    }
+
+    // When the old tree is complete its root is the first node of the path
+    let mut path: Vec<&[u8]> = Vec::new();
+    if old_size.is_power_of_two() {
+        path.push(old_root);
+    }
+    path.extend(proof.iter().map(|hash| hash.as_slice()));
+
+    let mut fnode: usize = old_size - 1;
+    let mut snode: usize = new_size - 1;
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    let mut fr: Hash = path[0].to_vec();
+    let mut sr: Hash = path[0].to_vec();
+    for c in &path[1..] {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && fr == old_root && sr == new_root
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub root_hash: Hash,
+    #[serde(with = "ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub sig: Sig,
+}
+
+impl SignedTreeHead {
+    pub fn new(
+        tree_size: u64,
+        root_hash: Hash,
+        timestamp: DateTime<Utc>,
+        prv_key: &PKey<Private>,
+    ) -> std::result::Result<SignedTreeHead, ErrorStack> {
+        let data: Vec<u8> = SignedTreeHead::args_to_signable(tree_size, &root_hash, timestamp);
+        let mut signer = Signer::new_without_digest(prv_key)?;
+        let sig: Sig = signer.sign_oneshot_to_vec(&data)?;
+        Ok(SignedTreeHead {
+            tree_size,
+            root_hash,
+            timestamp,
+            sig,
+        })
+    }
+
+    pub fn verify_sig(&self, pub_key: &PKey<Public>) -> bool {
+        let data: Vec<u8> =
+            SignedTreeHead::args_to_signable(self.tree_size, &self.root_hash, self.timestamp);
+        match Verifier::new_without_digest(pub_key) {
+            Err(_) => false,
+            Ok(mut verify) => verify.verify_oneshot(&self.sig, &data).unwrap_or(false),
+        }
+    }
+
+    // Bytes covered by the signature
+    pub fn signable(&self) -> Vec<u8> {
+        SignedTreeHead::args_to_signable(self.tree_size, &self.root_hash, self.timestamp)
+    }
+
+    fn args_to_signable(tree_size: u64, root_hash: &Hash, timestamp: DateTime<Utc>) -> Vec<u8> {
+        CanonicalEncoder::new(TREE_HEAD_DOMAIN)
+            .put_u64(tree_size)
+            .put_time(timestamp)
+            .put_bytes(root_hash)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::keys::generate_ed25519;
+
+    // Leaves and expected hashes from the certificate transparency reference
+    // tests for RFC 6962, as used for the RFC 9162 algorithms as well
+    const LEAVES: [&str; 8] = [
+        "",
+        "00",
+        "10",
+        "2021",
+        "3031",
+        "40414243",
+        "5051525354555657",
+        "606162636465666768696a6b6c6d6e6f",
+    ];
+
+    // ROOTS[n - 1] is the root of the tree of the first n leaves
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    // (leaf index, tree size, audit path)
+    const INCLUSION_PROOFS: [(usize, usize, &[&str]); 5] = [
+        (0, 1, &[]),
+        (
+            0,
+            8,
+            &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        ),
+        (
+            5,
+            8,
+            &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+        (
+            2,
+            3,
+            &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+        ),
+        (
+            1,
+            5,
+            &[
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        ),
+    ];
+
+    // (old size, new size, consistency proof)
+    const CONSISTENCY_PROOFS: [(usize, usize, &[&str]); 4] = [
+        (1, 1, &[]),
+        (
+            1,
+            8,
+            &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        ),
+        (
+            6,
+            8,
+            &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+        (
+            2,
+            5,
+            &[
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        ),
+    ];
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn hexes(hashes: &[&str]) -> Vec<Hash> {
+        hashes.iter().map(|hash| hex(hash)).collect()
+    }
+
+    fn tree_of(n: usize) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        for leaf in &LEAVES[..n] {
+            tree.push(&hex(leaf));
+        }
+        tree
+    }
+
+    fn root(n: usize) -> Hash {
+        hex(ROOTS[n - 1])
+    }
+
+    // Same hash with its first byte changed
+    fn tampered(hash: &[u8]) -> Hash {
+        let mut hash: Hash = hash.to_vec();
+        hash[0] ^= 0x01;
+        hash
+    }
+
+    #[test]
+    fn empty_tree_root_is_hash_of_nothing() {
+        assert_eq!(
+            MerkleTree::new().root(),
+            hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+    }
+
+    #[test]
+    fn roots_match_reference_vectors() {
+        let tree: MerkleTree = tree_of(8);
+        for n in 1..=8 {
+            assert_eq!(tree.root_at(n).unwrap(), root(n), "size {n}");
+            assert_eq!(tree_of(n).root(), root(n), "size {n}");
+        }
+        assert_eq!(tree.root_at(9), None);
+    }
+
+    #[test]
+    fn inclusion_proofs_match_reference_vectors() {
+        let tree: MerkleTree = tree_of(8);
+        for (index, size, path) in INCLUSION_PROOFS {
+            let path: Vec<Hash> = hexes(path);
+            assert_eq!(
+                tree.inclusion_proof(index, size).unwrap(),
+                path,
+                "leaf {index} of {size}"
+            );
+            let leaf: Hash = leaf_hash(&hex(LEAVES[index]));
+            assert!(
+                verify_inclusion(&leaf, index, size, &path, &root(size)),
+                "leaf {index} of {size}"
+            );
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_match_reference_vectors() {
+        let tree: MerkleTree = tree_of(8);
+        for (old_size, new_size, proof) in CONSISTENCY_PROOFS {
+            let proof: Vec<Hash> = hexes(proof);
+            assert_eq!(
+                tree.consistency_proof(old_size, new_size).unwrap(),
+                proof,
+                "{old_size} to {new_size}"
+            );
+            assert!(
+                verify_consistency(old_size, new_size, &root(old_size), &root(new_size), &proof),
+                "{old_size} to {new_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_proof_in_a_small_tree_verifies() {
+        let tree: MerkleTree = tree_of(8);
+        for new_size in 1..=8 {
+            for index in 0..new_size {
+                let proof: Vec<Hash> = tree.inclusion_proof(index, new_size).unwrap();
+                assert!(verify_inclusion(
+                    &tree.leaves()[index],
+                    index,
+                    new_size,
+                    &proof,
+                    &root(new_size)
+                ));
+            }
+            for old_size in 0..=new_size {
+                let proof: Vec<Hash> = tree.consistency_proof(old_size, new_size).unwrap();
+                assert!(verify_consistency(
+                    old_size,
+                    new_size,
+                    &tree.root_at(old_size).unwrap(),
+                    &root(new_size),
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn inclusion_fails_against_tampered_root_or_path() {
+        let (index, size, path) = INCLUSION_PROOFS[2];
+        let path: Vec<Hash> = hexes(path);
+        let leaf: Hash = leaf_hash(&hex(LEAVES[index]));
+        assert!(!verify_inclusion(
+            &leaf,
+            index,
+            size,
+            &path,
+            &tampered(&root(size))
+        ));
+        assert!(!verify_inclusion(
+            &tampered(&leaf),
+            index,
+            size,
+            &path,
+            &root(size)
+        ));
+        for i in 0..path.len() {
+            let mut bad_path: Vec<Hash> = path.clone();
+            bad_path[i] = tampered(&bad_path[i]);
+            assert!(!verify_inclusion(
+                &leaf,
+                index,
+                size,
+                &bad_path,
+                &root(size)
+            ));
+        }
+        assert!(!verify_inclusion(
+            &leaf,
+            index - 1,
+            size,
+            &path,
+            &root(size)
+        ));
+        // A size with a different path shape, leaf 5 sits in the same place
+        // in a tree of 7 as in a tree of 8 so that one still verifies. The
+        // signed tree head is what ties a root to its size.
+        assert!(!verify_inclusion(
+            &leaf,
+            index,
+            size / 2 + 2,
+            &path,
+            &root(size)
+        ));
+        assert!(!verify_inclusion(
+            &leaf,
+            index,
+            size,
+            &path[..2],
+            &root(size)
+        ));
+        assert!(!verify_inclusion(&leaf, size, size, &path, &root(size)));
+    }
+
+    #[test]
+    fn consistency_fails_against_tampered_roots_or_proof() {
+        let (old_size, new_size, proof) = CONSISTENCY_PROOFS[2];
+        let proof: Vec<Hash> = hexes(proof);
+        let (old_root, new_root) = (root(old_size), root(new_size));
+        assert!(!verify_consistency(
+            old_size,
+            new_size,
+            &tampered(&old_root),
+            &new_root,
+            &proof
+        ));
+        assert!(!verify_consistency(
+            old_size,
+            new_size,
+            &old_root,
+            &tampered(&new_root),
+            &proof
+        ));
+        for i in 0..proof.len() {
+            let mut bad_proof: Vec<Hash> = proof.clone();
+            bad_proof[i] = tampered(&bad_proof[i]);
+            assert!(!verify_consistency(
+                old_size, new_size, &old_root, &new_root, &bad_proof
+            ));
+        }
+        assert!(!verify_consistency(
+            old_size,
+            new_size,
+            &old_root,
+            &new_root,
+            &proof[..2]
+        ));
+        assert!(!verify_consistency(
+            old_size,
+            new_size,
+            &old_root,
+            &new_root,
+            &[]
+        ));
+        assert!(!verify_consistency(
+            new_size, old_size, &new_root, &old_root, &proof
+        ));
+    }
+
+    #[test]
+    fn tree_head_signature_covers_size_and_root() {
+        let (pub_key, prv_key) = generate_ed25519();
+        let tree_head: SignedTreeHead =
+            SignedTreeHead::new(8, root(8), Utc::now(), &prv_key).unwrap();
+        assert!(tree_head.verify_sig(&pub_key));
+
+        let mut bad_root: SignedTreeHead = tree_head.clone();
+        bad_root.root_hash = tampered(&bad_root.root_hash);
+        assert!(!bad_root.verify_sig(&pub_key));
+
+        let mut bad_size: SignedTreeHead = tree_head.clone();
+        bad_size.tree_size = 7;
+        assert!(!bad_size.verify_sig(&pub_key));
+
+        let (other_key, _) = generate_ed25519();
+        assert!(!tree_head.verify_sig(&other_key));
+    }
+}
@@ -1,4 +1,13 @@
+pub mod canonical;
 pub mod common;
 pub mod endorsement;
+pub mod graph;
 pub mod log;
+pub mod log_key;
+pub mod merkle;
+pub mod policy;
+pub mod pow;
 pub mod reid;
+pub mod revocation;
+pub mod segment;
+pub mod storage;
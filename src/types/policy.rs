@@ -0,0 +1,351 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use openssl::base64::decode_block;
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use super::common::{ClaimType, Id};
+use super::graph::{TrustEdge, TrustGraph};
+
+// How endorsements past their expiration are treated
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryPolicy {
+    #[default]
+    Strict, // expired endorsements don't count, after grace_days
+    Ignore, // expiration is not checked at all
+}
+
+// Rules for deciding whether to trust an identity based on the chains of
+// endorsements leading to it from a set of trusted roots. Lives in the
+// [policies.<name>] tables of graphlog.toml, e.g.
+//
+//   [policies.default]
+//   roots = ["<base64 id>"]
+//   max_path_len = 2
+//   min_paths = 2
+//   required_claims = ["SSHKEY"]
+//   expiry = "strict"
+//   grace_days = 7
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrustPolicy {
+    pub roots: Vec<String>, // base64 ids trusted without any endorsement
+    #[serde(default = "default_max_path_len")]
+    pub max_path_len: usize,
+    #[serde(
+        default = "default_min_paths",
+        deserialize_with = "deserialize_min_paths"
+    )]
+    pub min_paths: usize, // paths that share no identity besides the target, at least 1
+    #[serde(default)]
+    pub required_claims: Vec<ClaimType>, // the last endorsement must vouch for all of these
+    #[serde(default)]
+    pub expiry: ExpiryPolicy,
+    #[serde(default)]
+    pub grace_days: u32,
+}
+
+fn default_max_path_len() -> usize {
+    3
+}
+
+fn default_min_paths() -> usize {
+    1
+}
+
+// No paths required would trust every identity, so a 0 in graphlog.toml is
+// refused rather than taken at its word
+fn deserialize_min_paths<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match usize::deserialize(deserializer)? {
+        0 => Err(de::Error::custom(
+            "min_paths must be at least 1, 0 would trust every identity",
+        )),
+        min_paths => Ok(min_paths),
+    }
+}
+
+// Outcome of evaluating a policy, paths are the endorsement chains that
+// justify it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Verdict {
+    pub trusted: bool,
+    pub reason: String,
+    pub paths: Vec<Vec<TrustEdge>>,
+}
+
+impl TrustPolicy {
+    pub fn root_ids(&self) -> Result<Vec<Id>, String> {
+        self.roots
+            .iter()
+            .map(|root| decode_block(root).map_err(|why| format!("Bad root id {root}: {why}")))
+            .collect()
+    }
+
+    // Whether an endorsement still counts at `at`, revocations take effect
+    // immediately whatever the expiry handling
+    pub fn edge_live(&self, edge: &TrustEdge, at: DateTime<Utc>) -> bool {
+        if edge.is_revoked_at(at) {
+            return false;
+        }
+        match self.expiry {
+            ExpiryPolicy::Ignore => true,
+            ExpiryPolicy::Strict => edge.expiration + Duration::days(self.grace_days as i64) > at,
+        }
+    }
+
+    // Whether an endorsement into the target vouches for every required claim
+    pub fn edge_has_required_claims(&self, edge: &TrustEdge) -> bool {
+        self.required_claims
+            .iter()
+            .all(|required| edge.claims.iter().any(|(ct, _)| ct == required))
+    }
+
+    // Independent paths are found greedily, each shortest path found takes
+    // its identities out of the graph before searching for the next one.
+    // This can miss a set of disjoint paths that exists when an early short
+    // path blocks two longer ones, which errs on the side of not trusting.
+    pub fn evaluate(&self, graph: &TrustGraph, target: &Id, at: DateTime<Utc>) -> Verdict {
+        let roots: Vec<Id> = match self.root_ids() {
+            Err(why) => return Verdict::untrusted(why, Vec::new()),
+            Ok(roots) => roots,
+        };
+        if self.min_paths == 0 {
+            return Verdict::untrusted(
+                String::from("Policy requires no paths, min_paths must be at least 1"),
+                Vec::new(),
+            );
+        }
+        if roots.contains(target) {
+            return Verdict {
+                trusted: true,
+                reason: String::from("Target is a trusted root"),
+                paths: Vec::new(),
+            };
+        }
+
+        let graph: TrustGraph = graph.filtered(|edge| {
+            self.edge_live(edge, at) && (&edge.to != target || self.edge_has_required_claims(edge))
+        });
+        let mut avoid: HashSet<Id> = HashSet::new();
+        let mut paths: Vec<Vec<TrustEdge>> = Vec::new();
+        while paths.len() < self.min_paths {
+            let shortest: Option<Vec<TrustEdge>> = roots
+                .iter()
+                .filter(|root| !avoid.contains(*root))
+                .filter_map(|root| {
+                    graph.shortest_path_avoiding(root, target, &avoid, self.max_path_len)
+                })
+                .min_by_key(|path| path.len());
+            let path: Vec<TrustEdge> = match shortest {
+                None => break,
+                Some(path) => path,
+            };
+            for edge in &path {
+                avoid.insert(edge.from.clone());
+            }
+            paths.push(path);
+        }
+
+        if paths.len() >= self.min_paths {
+            Verdict {
+                trusted: true,
+                reason: format!(
+                    "Found {} independent endorsement path(s) from the trusted roots",
+                    paths.len()
+                ),
+                paths,
+            }
+        } else {
+            Verdict::untrusted(
+                format!(
+                    "Found {} of the {} independent endorsement path(s) required within {} hops",
+                    paths.len(),
+                    self.min_paths,
+                    self.max_path_len
+                ),
+                paths,
+            )
+        }
+    }
+}
+
+impl Verdict {
+    fn untrusted(reason: String, paths: Vec<Vec<TrustEdge>>) -> Verdict {
+        Verdict {
+            trusted: false,
+            reason,
+            paths,
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.trusted {
+            writeln!(f, "trusted: {}", self.reason)?;
+        } else {
+            writeln!(f, "not trusted: {}", self.reason)?;
+        }
+        for (i, path) in self.paths.iter().enumerate() {
+            writeln!(f, "path {}:", i + 1)?;
+            for edge in path {
+                writeln!(f, "{edge}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::common::KeyType;
+    use openssl::base64::encode_block;
+
+    fn id(n: u8) -> Id {
+        vec![n; 32]
+    }
+
+    fn edge(from: u8, to: u8, expiration: DateTime<Utc>) -> TrustEdge {
+        TrustEdge {
+            from: id(from),
+            to: id(to),
+            claims: vec![(ClaimType::SSHKEY, (KeyType::ED25519, format!("key-{to}")))],
+            expiration,
+            endorsement: vec![from, to],
+            revoked_at: None,
+        }
+    }
+
+    fn graph_of(edges: Vec<TrustEdge>) -> TrustGraph {
+        let mut graph = TrustGraph::new();
+        for edge in edges {
+            graph.add_edge(edge);
+        }
+        graph
+    }
+
+    fn policy(roots: &[u8], min_paths: usize) -> TrustPolicy {
+        TrustPolicy {
+            roots: roots.iter().map(|root| encode_block(&id(*root))).collect(),
+            max_path_len: default_max_path_len(),
+            min_paths,
+            required_claims: Vec::new(),
+            expiry: ExpiryPolicy::Strict,
+            grace_days: 0,
+        }
+    }
+
+    fn in_a_year() -> DateTime<Utc> {
+        Utc::now() + Duration::days(365)
+    }
+
+    #[test]
+    fn roots_are_trusted_without_endorsements() {
+        let verdict: Verdict = policy(&[1], 1).evaluate(&TrustGraph::new(), &id(1), Utc::now());
+        assert!(verdict.trusted);
+        assert!(verdict.paths.is_empty());
+        assert!(
+            !policy(&[1], 1)
+                .evaluate(&TrustGraph::new(), &id(2), Utc::now())
+                .trusted
+        );
+    }
+
+    #[test]
+    fn paths_through_the_same_identity_count_once() {
+        // 1 -> 3 -> 9 and 2 -> 3 -> 9 both go through 3
+        let shared: TrustGraph = graph_of(vec![
+            edge(1, 3, in_a_year()),
+            edge(2, 3, in_a_year()),
+            edge(3, 9, in_a_year()),
+        ]);
+        let verdict: Verdict = policy(&[1, 2], 2).evaluate(&shared, &id(9), Utc::now());
+        assert!(!verdict.trusted);
+        assert_eq!(verdict.paths.len(), 1);
+        assert!(
+            policy(&[1, 2], 1)
+                .evaluate(&shared, &id(9), Utc::now())
+                .trusted
+        );
+
+        // 1 -> 3 -> 9 and 2 -> 4 -> 9 share nothing but the target
+        let disjoint: TrustGraph = graph_of(vec![
+            edge(1, 3, in_a_year()),
+            edge(2, 4, in_a_year()),
+            edge(3, 9, in_a_year()),
+            edge(4, 9, in_a_year()),
+        ]);
+        let verdict: Verdict = policy(&[1, 2], 2).evaluate(&disjoint, &id(9), Utc::now());
+        assert!(verdict.trusted);
+        assert_eq!(verdict.paths.len(), 2);
+    }
+
+    #[test]
+    fn paths_longer_than_max_path_len_do_not_count() {
+        let graph: TrustGraph = graph_of(vec![
+            edge(1, 2, in_a_year()),
+            edge(2, 3, in_a_year()),
+            edge(3, 9, in_a_year()),
+        ]);
+        let mut policy: TrustPolicy = policy(&[1], 1);
+        assert!(policy.evaluate(&graph, &id(9), Utc::now()).trusted);
+        policy.max_path_len = 2;
+        assert!(!policy.evaluate(&graph, &id(9), Utc::now()).trusted);
+    }
+
+    #[test]
+    fn last_endorsement_must_vouch_for_required_claims() {
+        let graph: TrustGraph = graph_of(vec![edge(1, 9, in_a_year())]);
+        let mut policy: TrustPolicy = policy(&[1], 1);
+        policy.required_claims = vec![ClaimType::SSHKEY];
+        assert!(policy.evaluate(&graph, &id(9), Utc::now()).trusted);
+        policy.required_claims = vec![ClaimType::SSHKEY, ClaimType::X509];
+        assert!(!policy.evaluate(&graph, &id(9), Utc::now()).trusted);
+    }
+
+    #[test]
+    fn expired_endorsements_count_only_within_grace_or_when_ignored() {
+        let expired: TrustGraph = graph_of(vec![edge(1, 9, Utc::now() - Duration::days(2))]);
+        let mut policy: TrustPolicy = policy(&[1], 1);
+        assert!(!policy.evaluate(&expired, &id(9), Utc::now()).trusted);
+        policy.grace_days = 1;
+        assert!(!policy.evaluate(&expired, &id(9), Utc::now()).trusted);
+        policy.grace_days = 3;
+        assert!(policy.evaluate(&expired, &id(9), Utc::now()).trusted);
+        policy.grace_days = 0;
+        policy.expiry = ExpiryPolicy::Ignore;
+        assert!(policy.evaluate(&expired, &id(9), Utc::now()).trusted);
+    }
+
+    #[test]
+    fn revoked_endorsements_never_count() {
+        let mut revoked: TrustEdge = edge(1, 9, in_a_year());
+        revoked.revoked_at = Some(Utc::now() - Duration::hours(1));
+        let graph: TrustGraph = graph_of(vec![revoked]);
+        let mut policy: TrustPolicy = policy(&[1], 1);
+        policy.expiry = ExpiryPolicy::Ignore;
+        assert!(!policy.evaluate(&graph, &id(9), Utc::now()).trusted);
+        // Still counted when evaluated as of before the revocation
+        let before: DateTime<Utc> = Utc::now() - Duration::hours(2);
+        assert!(policy.evaluate(&graph, &id(9), before).trusted);
+    }
+
+    #[test]
+    fn min_paths_of_zero_is_rejected() {
+        let table: &str = "roots = []\nmin_paths = 0\n";
+        assert!(toml::from_str::<TrustPolicy>(table).is_err());
+        let table: &str = "roots = []\nmin_paths = 2\n";
+        assert_eq!(toml::from_str::<TrustPolicy>(table).unwrap().min_paths, 2);
+        let table: &str = "roots = []\n";
+        assert_eq!(toml::from_str::<TrustPolicy>(table).unwrap().min_paths, 1);
+
+        // Built in code rather than loaded, evaluate still refuses it
+        let verdict: Verdict = policy(&[1], 0).evaluate(&TrustGraph::new(), &id(9), Utc::now());
+        assert!(!verdict.trusted);
+    }
+}
@@ -0,0 +1,83 @@
+use openssl::sha::Sha256;
+
+use super::canonical::{CanonicalEncoder, POW_DOMAIN};
+
+// Hashcash style proof of work. The proof is an 8 byte nonce such that
+// SHA-256(canonical(POW_DOMAIN, data, nonce)) starts with at least
+// `difficulty` zero bits. Every extra bit doubles the expected work.
+
+fn pow_hash(data: &[u8], nonce: &[u8]) -> [u8; 32] {
+    let preimage: Vec<u8> = CanonicalEncoder::new(POW_DOMAIN)
+        .put_bytes(data)
+        .put_bytes(nonce)
+        .finish();
+    let mut hasher = Sha256::new();
+    hasher.update(&preimage);
+    hasher.finish()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits: u32 = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+pub fn solve(data: &[u8], difficulty: u8) -> Vec<u8> {
+    let mut nonce: u64 = 0;
+    loop {
+        let nonce_raw: [u8; 8] = nonce.to_be_bytes();
+        if leading_zero_bits(&pow_hash(data, &nonce_raw)) >= difficulty as u32 {
+            return nonce_raw.to_vec();
+        }
+        nonce += 1;
+    }
+}
+
+pub fn verify(data: &[u8], nonce: &[u8], difficulty: u8) -> bool {
+    difficulty == 0 || leading_zero_bits(&pow_hash(data, nonce)) >= difficulty as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff, 0]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0]), 7);
+        assert_eq!(leading_zero_bits(&[0, 0, 0x20, 0]), 18);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+
+    #[test]
+    fn solved_nonce_verifies() {
+        for difficulty in [0, 1, 8, 12] {
+            let nonce: Vec<u8> = solve(b"graphlog", difficulty);
+            assert_eq!(nonce.len(), 8);
+            assert!(verify(b"graphlog", &nonce, difficulty));
+            assert!(leading_zero_bits(&pow_hash(b"graphlog", &nonce)) >= difficulty as u32);
+        }
+    }
+
+    #[test]
+    fn wrong_nonce_is_rejected() {
+        let nonce: Vec<u8> = solve(b"graphlog", 12);
+        let solved: u64 = u64::from_be_bytes(nonce.clone().try_into().unwrap());
+        // solve returns the first nonce that works, so every one before it
+        // falls short
+        assert!(solved > 0);
+        for wrong in 0..solved.min(1000) {
+            assert!(!verify(b"graphlog", &wrong.to_be_bytes(), 12));
+        }
+        // The work is bound to the data it was done for
+        assert!(!verify(b"graphlog!", &nonce, 12));
+        assert!(!verify(b"graphlog", &[], 12));
+        // No work is asked for at difficulty 0
+        assert!(verify(b"graphlog", &[], 0));
+    }
+}
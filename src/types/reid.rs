@@ -1,6 +1,8 @@
-use crate::types::common::KeyType;
+use crate::types::common::{id_equal, KeyType};
 
-use super::common::{Encodable, Decodable, Id, Key, Sig, AnchorType, ClaimType};
+use super::canonical::{CanonicalEncoder, REID_DOMAIN};
+use super::common::{Encodable, Decodable, Id, Indexable, Key, Sig, AnchorType, ClaimType};
+use super::pow;
 use chrono::serde::ts_seconds;
 use chrono::{DateTime, Utc};
 use openssl::base64::{decode_block, encode_block};
@@ -8,7 +10,8 @@ use openssl::sign::Verifier;
 use openssl::{
     error::ErrorStack,
     hash::{Hasher, MessageDigest},
-    pkey::{PKey, Private, Public},
+    pkey::{self, PKey, Private, Public},
+    sha::sha256,
     sign::Signer,
 };
 use serde::{Deserialize, Serialize};
@@ -24,9 +27,14 @@ pub struct Reid {
     claims: Option<Vec<(ClaimType, Key)>>,
     anchors: Option<Vec<(AnchorType, String)>>,
     revoked: bool,
+    #[serde(default)]
+    pub_key: Vec<u8>, // raw Ed25519 public key that id is the hash of
+    #[serde(default)]
+    seq: u64, // must increase with every record published for id
 }
 
 impl Reid {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: Id,
         pow: Option<Vec<u8>>,
@@ -35,6 +43,8 @@ impl Reid {
         claims: Option<Vec<(ClaimType, Key)>>,
         anchors: Option<Vec<(AnchorType, String)>>,
         revoked: bool,
+        pub_key: Vec<u8>,
+        seq: u64,
     ) -> Self {
         Self {
             id,
@@ -44,9 +54,12 @@ impl Reid {
             claims,
             anchors,
             revoked,
+            pub_key,
+            seq,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new_with_keys(
         pub_key: &PKey<Public>,
         prv_key: &PKey<Private>,
@@ -55,38 +68,72 @@ impl Reid {
         claims: Option<Vec<(ClaimType, Key)>>,
         anchors: Option<Vec<(AnchorType, String)>>,
         revoked: bool,
+        seq: u64,
     ) -> Self {
-        // Generate ID which is just hash(public key)
-        let mut hasher = Hasher::new(MessageDigest::sha256()).unwrap();
         let pub_key_raw: Vec<u8> = match pub_key.raw_public_key() {
             Err(why) => {
                 panic!("Couldn't convert public key into raw bytes using raw_public_key: {why}")
             }
             Ok(pub_key_raw) => pub_key_raw,
         };
-        if let Err(why) = hasher.update(&pub_key_raw) {
-            panic!("Hasher updated failed: {why}");
-        }
-
-        let id: Id = match hasher.finish() {
-            Err(why) => panic!("Hashing public key failed: {why}"),
-            Ok(id) => id.to_vec(),
-        };
-
-        let sig: Sig = match Reid::sign_reid_with_args(prv_key, &id, expiration, &claims, &anchors)
-        {
-            Err(why) => panic!("Signing Reid failed: {why}"),
-            Ok(sig) => sig,
-        };
+        let id: Id = Reid::id_from_raw_key(&pub_key_raw);
 
-        Self {
+        let mut reid = Self {
             id,
             pow,
             expiration,
-            sig,
+            sig: Vec::new(),
             claims,
             anchors,
             revoked,
+            pub_key: pub_key_raw,
+            seq,
+        };
+        if let Err(why) = reid.update_sig(prv_key) {
+            panic!("Signing Reid failed: {why}");
+        }
+        reid
+    }
+
+    // Generate ID which is just hash(public key)
+    pub fn id_from_raw_key(pub_key_raw: &[u8]) -> Id {
+        let mut hasher = Hasher::new(MessageDigest::sha256()).unwrap();
+        if let Err(why) = hasher.update(pub_key_raw) {
+            panic!("Hasher updated failed: {why}");
+        }
+        match hasher.finish() {
+            Err(why) => panic!("Hashing public key failed: {why}"),
+            Ok(id) => id.to_vec(),
+        }
+    }
+
+    // Public key embedded in the reid, None for records published before
+    // keys were embedded or if the bytes are not a valid Ed25519 key
+    pub fn public_key(&self) -> Option<PKey<Public>> {
+        if self.pub_key.is_empty() {
+            return None;
+        }
+        PKey::public_key_from_raw_bytes(&self.pub_key, pkey::Id::ED25519).ok()
+    }
+
+    // Checks that id is the hash of pub_key and that the embedded key,
+    // if there is one, is that same key
+    pub fn verify_binding(&self, pub_key: &PKey<Public>) -> bool {
+        let pub_key_raw: Vec<u8> = match pub_key.raw_public_key() {
+            Err(_) => return false,
+            Ok(pub_key_raw) => pub_key_raw,
+        };
+        if !self.pub_key.is_empty() && self.pub_key != pub_key_raw {
+            return false;
+        }
+        id_equal(self.id.clone(), Reid::id_from_raw_key(&pub_key_raw))
+    }
+
+    // Self contained check using only the embedded public key
+    pub fn verify(&self) -> bool {
+        match self.public_key() {
+            None => false,
+            Some(pub_key) => self.verify_binding(&pub_key) && self.verify_sig(&pub_key),
         }
     }
 
@@ -108,9 +155,7 @@ impl Reid {
 
     pub fn key_to_pem(key: &Key) -> String {
         let key_value: &String = &key.1;
-        if key.0 == KeyType::ED25519 {
-            key_value.clone()
-        } else if key.0 == KeyType::CHACHA20POLY1305 {
+        if key.0 == KeyType::ED25519 || key.0 == KeyType::CHACHA20POLY1305 {
             key_value.clone()
         } else {
             println!("Displaying unsupported key type, using base64");
@@ -122,92 +167,191 @@ impl Reid {
         self.id.clone()
     }
 
+    // Base64 sha256 of a claimed key. An OpenSSH public key is hashed over
+    // its decoded key blob like ssh-keygen -l does, so the comment after it
+    // doesn't change the fingerprint. Any other key is hashed as text with
+    // surrounding whitespace such as the newline at the end of a key file
+    // ignored.
+    pub fn key_fingerprint(key_value: &str) -> String {
+        match Reid::ssh_key_blob(key_value) {
+            Some(blob) => encode_block(&sha256(&blob)),
+            None => encode_block(&sha256(key_value.trim().as_bytes())),
+        }
+    }
+
+    // Key blob of an OpenSSH public key line, "<type> <base64 blob> [comment]",
+    // None if key_value isn't one. The blob starts with the key type again,
+    // which has to match the first field.
+    fn ssh_key_blob(key_value: &str) -> Option<Vec<u8>> {
+        let mut fields = key_value.split_whitespace();
+        let (key_type, blob_b64) = (fields.next()?, fields.next()?);
+        let blob: Vec<u8> = decode_block(blob_b64).ok()?;
+        let type_len: usize = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+        let blob_type: &[u8] = blob.get(4..4usize.checked_add(type_len)?)?;
+        (blob_type == key_type.as_bytes()).then_some(blob)
+    }
+
+    // Search terms for the log index, see Indexable::search_terms
+    pub fn anchor_term(anchor_type: &AnchorType, value: &str) -> String {
+        format!("anchor:{anchor_type:?}:{}", anchor_type.normalize(value))
+    }
+
+    pub fn claim_term(key_value: &str) -> String {
+        format!("claim:{}", Reid::key_fingerprint(key_value))
+    }
+
     pub fn update_sig(&mut self, prv_key: &PKey<Private>) -> std::result::Result<Sig, ErrorStack> {
-        Reid::sign_reid(prv_key, self)
+        let sig: Sig = Reid::sign_reid(prv_key, self)?;
+        self.sig = sig.clone();
+        Ok(sig)
     }
 
-    pub fn revoke(&mut self) {
+    // Revoking changes what the record asserts so it has to be signed again,
+    // otherwise anyone relaying the record could flip the flag
+    pub fn revoke(&mut self, prv_key: &PKey<Private>) -> std::result::Result<Sig, ErrorStack> {
         self.revoked = true;
+        self.update_sig(prv_key)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked
+    }
+
+    pub fn get_seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn get_expiration(&self) -> DateTime<Utc> {
+        self.expiration
+    }
+
+    pub fn get_claims(&self) -> &Option<Vec<(ClaimType, Key)>> {
+        &self.claims
+    }
+
+    pub fn get_anchors(&self) -> &Option<Vec<(AnchorType, String)>> {
+        &self.anchors
     }
 
     fn sign_reid(
         prv_key: &PKey<Private>,
         reid: &Reid,
     ) -> std::result::Result<Sig, openssl::error::ErrorStack> {
-        let mut signer = Signer::new_without_digest(prv_key).unwrap();
+        let mut signer = Signer::new_without_digest(prv_key)?;
         let reid_data: Vec<u8> = Reid::reid_to_signable(reid); // Char vector that will be signed
-        let mut sig = vec![0u8; reid_data.len()];
-        if let Err(why) = signer.sign_oneshot(&mut sig, &reid_data) {
-            panic!("Signer failed {why}")
-        };
-        Ok(sig.to_vec())
-    }
-
-    fn sign_reid_with_args(
-        prv_key: &PKey<Private>,
-        id: &Id,
-        expiration: DateTime<Utc>,
-        claims: &Option<Vec<(ClaimType, Key)>>,
-        anchors: &Option<Vec<(AnchorType, String)>>,
-    ) -> std::result::Result<Sig, openssl::error::ErrorStack> {
-        let mut signer = Signer::new_without_digest(prv_key).unwrap();
-        let reid_data: Vec<u8> = Reid::args_to_signable(id, expiration, claims, anchors); // Char vector that will be signed
-        let mut sig = vec![0u8; Signer::len(&signer).unwrap()];
-        if let Err(why) = signer.sign_oneshot(&mut sig, &reid_data) {
-            panic!("Signer failed {why}")
-        };
-        Ok(sig.to_vec())
+        signer.sign_oneshot_to_vec(&reid_data)
     }
 
     pub fn verify_sig(&self, pub_key: &PKey<Public>) -> bool {
         let mut verify = Verifier::new_without_digest(pub_key).unwrap();
         let data: Vec<u8> = Reid::reid_to_signable(self);
-        verify.verify_oneshot(&self.sig, &data).unwrap()
+        verify.verify_oneshot(&self.sig, &data).unwrap_or(false)
+    }
+
+    // The proof of work covers everything the signature does except the
+    // proof itself, the signature then covers the proof
+    pub fn pow_preimage(&self) -> Vec<u8> {
+        Reid::args_to_signable(
+            &self.id,
+            &self.pub_key,
+            self.seq,
+            self.expiration,
+            &self.claims,
+            &self.anchors,
+            self.revoked,
+            &None,
+        )
+    }
+
+    pub fn solve_pow(
+        &mut self,
+        difficulty: u8,
+        prv_key: &PKey<Private>,
+    ) -> std::result::Result<Sig, ErrorStack> {
+        self.pow = Some(pow::solve(&self.pow_preimage(), difficulty));
+        self.update_sig(prv_key)
+    }
+
+    pub fn verify_pow(&self, difficulty: u8) -> bool {
+        match &self.pow {
+            None => difficulty == 0,
+            Some(nonce) => pow::verify(&self.pow_preimage(), nonce, difficulty),
+        }
+    }
+
+    // Bytes covered by the signature
+    pub fn signable(&self) -> Vec<u8> {
+        Reid::reid_to_signable(self)
     }
 
     fn reid_to_signable(reid: &Reid) -> Vec<u8> {
-        Reid::args_to_signable(&reid.id, reid.expiration, &reid.claims, &reid.anchors)
+        Reid::args_to_signable(
+            &reid.id,
+            &reid.pub_key,
+            reid.seq,
+            reid.expiration,
+            &reid.claims,
+            &reid.anchors,
+            reid.revoked,
+            &reid.pow,
+        )
     }
 
+    // Every field except the signature itself is covered, see
+    // canonical.rs for the byte layout
+    #[allow(clippy::too_many_arguments)]
     fn args_to_signable(
         id: &Id,
+        pub_key: &[u8],
+        seq: u64,
         expiration: DateTime<Utc>,
         claims: &Option<Vec<(ClaimType, Key)>>,
         anchors: &Option<Vec<(AnchorType, String)>>,
+        revoked: bool,
+        pow: &Option<Vec<u8>>,
     ) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.extend(id.clone().iter());
-
-        let claims_clone: Option<Vec<(ClaimType, Key)>> = claims.clone();
-        if let Some(claim_val) = claims_clone {
-            let claim_raw: Vec<u8> = claim_val
-                .into_iter()
-                .flat_map(|(ct, k)| {
-                    let mut combined: Vec<u8> = vec![ct as u8];
-                    combined.push(k.0 as u8);
-                    combined.extend(k.1.clone().into_bytes());
-                    combined
-                })
-                .collect();
-            data.extend(claim_raw);
-        }
+        CanonicalEncoder::new(REID_DOMAIN)
+            .put_bytes(id)
+            .put_bytes(pub_key)
+            .put_u64(seq)
+            .put_time(expiration)
+            .put_option(claims, |enc, claims| {
+                enc.put_list(claims, |enc, (ct, k)| {
+                    enc.put_u8(ct.clone() as u8)
+                        .put_u8(k.0.clone() as u8)
+                        .put_str(&k.1);
+                });
+            })
+            .put_option(anchors, |enc, anchors| {
+                enc.put_list(anchors, |enc, (at, av)| {
+                    enc.put_u8(at.clone() as u8).put_str(av);
+                });
+            })
+            .put_bool(revoked)
+            .put_option(pow, |enc, pow| {
+                enc.put_bytes(pow);
+            })
+            .finish()
+    }
+}
 
-        let anchors_clone: Option<Vec<(AnchorType, String)>> = anchors.clone();
-        if let Some(anchor_val) = anchors_clone {
-            let anchor_raw: Vec<u8> = anchor_val
-                .into_iter()
-                .flat_map(|(at, av)| {
-                    let mut combined: Vec<u8> = vec![at as u8];
-                    combined.extend(av.into_bytes());
-                    combined
-                })
-                .collect();
-            data.extend(anchor_raw);
-        }
+impl Indexable for Reid {
+    fn index_ids(&self) -> Vec<Id> {
+        vec![self.id.clone()]
+    }
 
-        let exp_raw: Vec<u8> = expiration.clone().to_rfc3339().into_bytes();
-        data.extend(exp_raw);
-        data
+    fn search_terms(&self) -> Vec<String> {
+        let anchors = self
+            .anchors
+            .iter()
+            .flatten()
+            .map(|(anchor_type, value)| Reid::anchor_term(anchor_type, value));
+        let claims = self
+            .claims
+            .iter()
+            .flatten()
+            .map(|(_, key)| Reid::claim_term(&key.1));
+        anchors.chain(claims).collect()
     }
 }
 
@@ -250,6 +394,9 @@ impl fmt::Display for Reid {
         // id
         writeln!(f, "id: {}", encode_block(&self.id))?;
 
+        // seq
+        writeln!(f, "seq: {}", self.seq)?;
+
         // pow
         match &self.pow {
             Some(pow) => writeln!(f, "pow: {}", encode_block(pow))?,
@@ -294,3 +441,217 @@ impl fmt::Display for Reid {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::keys::generate_ed25519;
+    use chrono::Duration;
+
+    type Keys = (PKey<Public>, PKey<Private>);
+
+    fn raw(keys: &Keys) -> Vec<u8> {
+        keys.0.raw_public_key().unwrap()
+    }
+
+    // Reid claiming `id` with `pub_key` embedded, signed by `keys`
+    fn signed(keys: &Keys, id: Id, pub_key: Vec<u8>) -> Reid {
+        let mut reid = Reid::new(
+            id,
+            None,
+            Utc::now() + Duration::days(1),
+            Vec::new(),
+            None,
+            None,
+            false,
+            pub_key,
+            1,
+        );
+        reid.update_sig(&keys.1).unwrap();
+        reid
+    }
+
+    #[test]
+    fn binding_accepts_the_key_the_id_is_derived_from() {
+        let keys: Keys = generate_ed25519();
+        let reid: Reid = signed(&keys, Reid::id_from_raw_key(&raw(&keys)), raw(&keys));
+        assert!(reid.verify_binding(&keys.0));
+        assert!(reid.verify());
+    }
+
+    #[test]
+    fn binding_rejects_an_id_of_another_key() {
+        let (keys, other): (Keys, Keys) = (generate_ed25519(), generate_ed25519());
+        // Properly signed, but claiming someone else's id
+        let reid: Reid = signed(&keys, Reid::id_from_raw_key(&raw(&other)), raw(&keys));
+        assert!(reid.verify_sig(&keys.0));
+        assert!(!reid.verify_binding(&keys.0));
+        assert!(!reid.verify());
+    }
+
+    #[test]
+    fn binding_rejects_another_key() {
+        let (keys, other): (Keys, Keys) = (generate_ed25519(), generate_ed25519());
+        let reid: Reid = signed(&keys, Reid::id_from_raw_key(&raw(&keys)), raw(&keys));
+        assert!(!reid.verify_binding(&other.0));
+
+        // Embedding a key other than the one the id is derived from
+        let reid: Reid = signed(&other, Reid::id_from_raw_key(&raw(&keys)), raw(&other));
+        assert!(!reid.verify_binding(&keys.0));
+        assert!(!reid.verify_binding(&other.0));
+        assert!(!reid.verify());
+    }
+
+    #[test]
+    fn binding_without_an_embedded_key_checks_the_id() {
+        let (keys, other): (Keys, Keys) = (generate_ed25519(), generate_ed25519());
+        let reid: Reid = signed(&keys, Reid::id_from_raw_key(&raw(&keys)), Vec::new());
+        assert!(reid.verify_binding(&keys.0));
+        assert!(!reid.verify_binding(&other.0));
+        // Nothing to verify against without the key
+        assert!(!reid.verify());
+    }
+
+    // Fully populated reid, so every signed field has something to change
+    fn full_reid(keys: &Keys) -> Reid {
+        let mut reid = Reid::new_with_keys(
+            &keys.0,
+            &keys.1,
+            Utc::now() + Duration::days(1),
+            Some(vec![0, 0, 0, 42]),
+            Some(vec![(
+                ClaimType::SSHKEY,
+                (KeyType::ED25519, "ssh-ed25519 AAAA".to_string()),
+            )]),
+            Some(vec![(AnchorType::EMAIL, "alice@example.com".to_string())]),
+            false,
+            3,
+        );
+        reid.update_sig(&keys.1).unwrap();
+        reid
+    }
+
+    // The signature has to stop verifying once `mutate` changes a field
+    fn assert_signed(mutate: impl FnOnce(&mut Reid)) {
+        let keys: Keys = generate_ed25519();
+        let mut reid: Reid = full_reid(&keys);
+        assert!(reid.verify());
+        mutate(&mut reid);
+        assert!(!reid.verify_sig(&keys.0));
+        assert!(!reid.verify());
+    }
+
+    #[test]
+    fn signature_covers_revoked() {
+        assert_signed(|reid| reid.revoked = true);
+    }
+
+    #[test]
+    fn signature_covers_pow() {
+        assert_signed(|reid| reid.pow = Some(vec![0, 0, 0, 43]));
+        assert_signed(|reid| reid.pow = None);
+    }
+
+    #[test]
+    fn signature_covers_seq() {
+        assert_signed(|reid| reid.seq += 1);
+    }
+
+    #[test]
+    fn signature_covers_expiration() {
+        assert_signed(|reid| reid.expiration += Duration::days(365));
+    }
+
+    #[test]
+    fn signature_covers_claims() {
+        assert_signed(|reid| reid.claims.as_mut().unwrap()[0].1 .1.push('='));
+        assert_signed(|reid| reid.claims.as_mut().unwrap()[0].1 .0 = KeyType::CHACHA20POLY1305);
+        assert_signed(|reid| reid.claims.as_mut().unwrap()[0].0 = ClaimType::X509);
+        assert_signed(|reid| {
+            reid.append_claim(
+                ClaimType::SSHKEY,
+                (KeyType::ED25519, "ssh-ed25519 BBBB".to_string()),
+            )
+        });
+        assert_signed(|reid| reid.claims = None);
+    }
+
+    #[test]
+    fn signature_covers_anchors() {
+        assert_signed(|reid| {
+            reid.anchors.as_mut().unwrap()[0].1 = "mallory@example.com".to_string()
+        });
+        assert_signed(|reid| reid.anchors.as_mut().unwrap()[0].0 = AnchorType::DNS);
+        assert_signed(|reid| reid.append_anchor(AnchorType::DNS, "example.com".to_string()));
+        assert_signed(|reid| reid.anchors = Some(Vec::new()));
+    }
+
+    // OpenSSH key blob: length prefixed type then length prefixed key
+    fn ssh_blob(key_type: &str, key: &[u8]) -> Vec<u8> {
+        let mut blob: Vec<u8> = Vec::new();
+        blob.extend((key_type.len() as u32).to_be_bytes());
+        blob.extend(key_type.as_bytes());
+        blob.extend((key.len() as u32).to_be_bytes());
+        blob.extend(key);
+        blob
+    }
+
+    fn ssh_line(key: &[u8], comment: &str) -> String {
+        format!(
+            "ssh-ed25519 {} {comment}",
+            encode_block(&ssh_blob("ssh-ed25519", key))
+        )
+    }
+
+    #[test]
+    fn ssh_fingerprint_ignores_the_comment() {
+        let key: [u8; 32] = [7; 32];
+        let fingerprint: String = Reid::key_fingerprint(&ssh_line(&key, "alice@laptop"));
+        // Same hash ssh-keygen -l shows, apart from the base64 padding
+        assert_eq!(
+            fingerprint,
+            encode_block(&sha256(&ssh_blob("ssh-ed25519", &key)))
+        );
+        for line in [
+            ssh_line(&key, "alice@desktop"),
+            ssh_line(&key, "a comment with spaces"),
+            ssh_line(&key, "").trim().to_string(),
+            format!("  {}\n", ssh_line(&key, "alice@laptop")),
+        ] {
+            assert_eq!(Reid::key_fingerprint(&line), fingerprint, "{line}");
+            assert_eq!(
+                Reid::claim_term(&line),
+                Reid::claim_term(&ssh_line(&key, "x"))
+            );
+        }
+        assert_ne!(
+            Reid::key_fingerprint(&ssh_line(&[8; 32], "alice@laptop")),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn other_keys_are_fingerprinted_as_text() {
+        let text = |key: &str| encode_block(&sha256(key.trim().as_bytes()));
+        let pem: &str = "-----BEGIN PUBLIC KEY-----\nMCowBQYDK2VwAyEA\n-----END PUBLIC KEY-----\n";
+        let blob_b64: String = encode_block(&ssh_blob("ssh-ed25519", &[7; 32]));
+        for key in [
+            pem,
+            "wireguard-key-in-base64=",
+            "",
+            // An OpenSSH line whose type doesn't match its blob
+            &format!("ssh-rsa {blob_b64} alice@laptop"),
+            "ssh-ed25519 not-base64! alice@laptop",
+            // Too short to hold a type
+            "ssh-ed25519 AAA= alice@laptop",
+        ] {
+            assert_eq!(Reid::key_fingerprint(key), text(key), "{key}");
+        }
+        // Only surrounding whitespace is ignored, so the comment counts
+        assert_eq!(Reid::key_fingerprint(&format!("{pem}\n\n")), text(pem));
+        assert_ne!(
+            Reid::key_fingerprint(&format!("ssh-rsa {blob_b64} alice@laptop")),
+            Reid::key_fingerprint(&format!("ssh-rsa {blob_b64} alice@desktop"))
+        );
+    }
+}
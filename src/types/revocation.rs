@@ -0,0 +1,170 @@
+use super::canonical::{CanonicalEncoder, REVOCATION_DOMAIN};
+use super::common::{id_equal, Decodable, Encodable, Id, Indexable, Sig};
+use super::endorsement::Endorsement;
+use super::reid::Reid;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use openssl::base64::{decode_block, encode_block};
+use openssl::error::ErrorStack;
+use openssl::pkey::{self, PKey, Private, Public};
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Withdraws an earlier endorsement from revoked_at onwards, only the
+// endorser can revoke their own endorsement
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EndorsementRevocation {
+    endorsing_id: Id,
+    endorsement: Vec<u8>, // digest of the endorsement being revoked
+    #[serde(with = "ts_seconds")]
+    revoked_at: DateTime<Utc>,
+    sig: Option<Sig>,
+    pub_key: Vec<u8>, // raw Ed25519 public key that endorsing_id is the hash of
+}
+
+impl EndorsementRevocation {
+    pub fn new_with_keys(
+        pub_key: &PKey<Public>,
+        prv_key: &PKey<Private>,
+        endorsement: &Endorsement,
+        revoked_at: DateTime<Utc>,
+    ) -> Self {
+        let pub_key_raw: Vec<u8> = match pub_key.raw_public_key() {
+            Err(why) => {
+                panic!("Couldn't convert public key into raw bytes using raw_public_key: {why}")
+            }
+            Ok(pub_key_raw) => pub_key_raw,
+        };
+        let mut revocation = Self {
+            endorsing_id: Reid::id_from_raw_key(&pub_key_raw),
+            endorsement: endorsement.digest(),
+            revoked_at,
+            sig: None,
+            pub_key: pub_key_raw,
+        };
+        if let Err(why) = revocation.update_sig(prv_key) {
+            panic!("Signing EndorsementRevocation failed: {why}");
+        }
+        revocation
+    }
+
+    pub fn get_endorsing_id(&self) -> Id {
+        self.endorsing_id.clone()
+    }
+
+    pub fn get_endorsement(&self) -> &[u8] {
+        &self.endorsement
+    }
+
+    pub fn get_revoked_at(&self) -> DateTime<Utc> {
+        self.revoked_at
+    }
+
+    // Whether this revocation withdraws endorsement, the endorser has to match
+    // as well as the digest so nobody can revoke someone else's endorsement
+    pub fn revokes(&self, endorsement: &Endorsement) -> bool {
+        id_equal(self.endorsing_id.clone(), endorsement.get_endorsing_id())
+            && self.endorsement == endorsement.digest()
+    }
+
+    // Public key embedded in the revocation, None if missing or invalid
+    pub fn public_key(&self) -> Option<PKey<Public>> {
+        PKey::public_key_from_raw_bytes(&self.pub_key, pkey::Id::ED25519).ok()
+    }
+
+    pub fn verify_binding(&self, pub_key: &PKey<Public>) -> bool {
+        match pub_key.raw_public_key() {
+            Err(_) => false,
+            Ok(pub_key_raw) => {
+                self.pub_key == pub_key_raw
+                    && id_equal(
+                        self.endorsing_id.clone(),
+                        Reid::id_from_raw_key(&pub_key_raw),
+                    )
+            }
+        }
+    }
+
+    // Self contained check using only the embedded public key
+    pub fn verify(&self) -> bool {
+        match self.public_key() {
+            None => false,
+            Some(pub_key) => self.verify_binding(&pub_key) && self.verify_sig(&pub_key),
+        }
+    }
+
+    pub fn update_sig(&mut self, prv_key: &PKey<Private>) -> std::result::Result<Sig, ErrorStack> {
+        let mut signer = Signer::new_without_digest(prv_key)?;
+        let sig: Sig = signer.sign_oneshot_to_vec(&self.signable())?;
+        self.sig = Some(sig.clone());
+        Ok(sig)
+    }
+
+    pub fn verify_sig(&self, pub_key: &PKey<Public>) -> bool {
+        let sig: &Sig = match &self.sig {
+            None => return false,
+            Some(sig) => sig,
+        };
+        let mut verify = Verifier::new_without_digest(pub_key).unwrap();
+        verify
+            .verify_oneshot(sig, &self.signable())
+            .unwrap_or(false)
+    }
+
+    // Bytes covered by the signature, see canonical.rs for the byte layout
+    pub fn signable(&self) -> Vec<u8> {
+        CanonicalEncoder::new(REVOCATION_DOMAIN)
+            .put_bytes(&self.endorsing_id)
+            .put_bytes(&self.pub_key)
+            .put_bytes(&self.endorsement)
+            .put_time(self.revoked_at)
+            .finish()
+    }
+}
+
+impl Indexable for EndorsementRevocation {
+    fn index_ids(&self) -> Vec<Id> {
+        vec![self.endorsing_id.clone()]
+    }
+}
+
+impl Encodable for EndorsementRevocation {
+    fn encode(&self) -> String {
+        encode_block(serde_json::to_string(&self).unwrap().as_bytes())
+    }
+}
+
+impl Decodable<EndorsementRevocation> for EndorsementRevocation {
+    fn decode(revocation_b64: &str) -> Option<EndorsementRevocation> {
+        let revocation_vec: Vec<u8> = match decode_block(revocation_b64) {
+            Err(why) => {
+                println!("Error decoding revocation base64: {why}");
+                return None;
+            }
+            Ok(vec) => vec,
+        };
+        let revocation_json: String = match String::from_utf8(revocation_vec) {
+            Err(why) => {
+                println!("Error parsing decoded base64 vector into string: {why}");
+                return None;
+            }
+            Ok(str) => str,
+        };
+        match serde_json::from_str(&revocation_json) {
+            Err(why) => {
+                println!("Error decoding revocation_json into EndorsementRevocation object: {why}");
+                None
+            }
+            Ok(revocation) => revocation,
+        }
+    }
+}
+
+impl fmt::Display for EndorsementRevocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "endorsing id: {}", encode_block(&self.endorsing_id))?;
+        writeln!(f, "endorsement: {}", encode_block(&self.endorsement))?;
+        writeln!(f, "revoked at: {}", self.revoked_at.to_rfc3339())
+    }
+}
@@ -0,0 +1,599 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use super::common::{Decodable, Encodable};
+use super::storage::{RecoveryReport, Storage};
+
+// Binary log made of a directory of segment files. Each segment is named
+// after the index of its first record, e.g. 00000000000000000042.seg, and
+// holds
+//
+//   header    magic "GLSG" || version (u8) || first record index (u64 BE)
+//   records   length (u32 BE) || crc32 of payload (u32 BE) || payload
+//
+// The payload is the record's Encodable form, the same bytes its merkle leaf
+// hash covers. A new segment is started once the current one would grow
+// past the configured size.
+pub const SEGMENT_MAGIC: &[u8; 4] = b"GLSG";
+pub const SEGMENT_VERSION: u8 = 1;
+pub const SEGMENT_HEADER_LEN: u64 = 13;
+pub const RECORD_HEADER_LEN: u64 = 8;
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+struct Segment {
+    base: u64, // index of the first record in the segment
+    path: PathBuf,
+    size: u64,
+}
+
+// Segment directory working on raw record bytes
+pub struct SegmentLog {
+    dir: PathBuf,
+    segment_bytes: u64,
+    segments: Vec<Segment>,
+    len: u64,
+    report: RecoveryReport,
+}
+
+impl SegmentLog {
+    // Opens or creates the segment directory, checking the header and every
+    // record checksum of the existing segments
+    pub fn open(dir: &Path, segment_bytes: u64) -> io::Result<SegmentLog> {
+        if dir.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is a text format log, convert it with the migrate tool",
+                    dir.display()
+                ),
+            ));
+        }
+        fs::create_dir_all(dir)?;
+        let mut log = SegmentLog {
+            dir: dir.to_path_buf(),
+            segment_bytes,
+            segments: Vec::new(),
+            len: 0,
+            report: RecoveryReport::default(),
+        };
+
+        let mut paths: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path: PathBuf = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "seg") {
+                if let Some(base) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    paths.push((base, path));
+                }
+            }
+        }
+        paths.sort();
+
+        let num_segments: usize = paths.len();
+        for (i, (base, path)) in paths.into_iter().enumerate() {
+            if base != log.len {
+                return Err(invalid_data(format!(
+                    "Segment {} starts at record {base} but the log has {} records before it",
+                    path.display(),
+                    log.len
+                )));
+            }
+            let scan: SegmentScan = SegmentLog::scan_segment(&path, base, |_| Ok(()))?;
+            let size: u64 = fs::metadata(&path)?.len();
+            if let Some(why) = scan.torn {
+                // Segments are synced before the next one is started so only
+                // the last one can have been cut off by a crash
+                if i + 1 != num_segments {
+                    return Err(invalid_data(format!(
+                        "Segment {} is damaged: {why}",
+                        path.display()
+                    )));
+                }
+                println!(
+                    "Truncating torn write at the end of {}: {why}",
+                    path.display()
+                );
+                log.report.truncated = Some((path.clone(), size - scan.valid_len));
+                if scan.valid_len < SEGMENT_HEADER_LEN {
+                    fs::remove_file(&path)?;
+                    break;
+                }
+                let file: File = File::options().write(true).open(&path)?;
+                file.set_len(scan.valid_len)?;
+                file.sync_all()?;
+            }
+            log.segments.push(Segment {
+                base,
+                path,
+                size: scan.valid_len,
+            });
+            log.len += scan.count;
+        }
+        log.report.entries = log.len as usize;
+        Ok(log)
+    }
+
+    // What had to be repaired when the log was opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.report
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Either every record is appended or, if writing fails, the segments are
+    // cut back to where they were before
+    pub fn append<R>(&mut self, records: &[R]) -> io::Result<()>
+    where
+        R: AsRef<[u8]>,
+    {
+        let num_segments: usize = self.segments.len();
+        let (len, size): (u64, u64) = (self.len, self.segments.last().map_or(0, |s| s.size));
+        let written: io::Result<()> = self.write_records(records);
+        if written.is_err() {
+            for segment in self.segments.drain(num_segments..) {
+                let _ = fs::remove_file(&segment.path);
+            }
+            if let Some(segment) = self.segments.last_mut() {
+                if let Ok(file) = File::options().write(true).open(&segment.path) {
+                    let _ = file.set_len(size);
+                }
+                segment.size = size;
+            }
+            self.len = len;
+        }
+        written
+    }
+
+    fn write_records<R>(&mut self, records: &[R]) -> io::Result<()>
+    where
+        R: AsRef<[u8]>,
+    {
+        let mut writer: Option<BufWriter<File>> = None;
+        for record in records {
+            let record: &[u8] = record.as_ref();
+            let record_len: u64 = RECORD_HEADER_LEN + record.len() as u64;
+            let roll: bool = match self.segments.last() {
+                None => true,
+                Some(segment) => {
+                    segment.size + record_len > self.segment_bytes
+                        && segment.size > SEGMENT_HEADER_LEN
+                }
+            };
+            if roll {
+                // A full segment is made durable before the next one exists
+                // so a crash can only ever tear the last segment
+                match writer.take() {
+                    Some(mut full) => {
+                        full.flush()?;
+                        full.get_ref().sync_all()?;
+                    }
+                    None => self.sync()?,
+                }
+                writer = Some(self.start_segment()?);
+            }
+            if writer.is_none() {
+                writer = Some(self.open_active()?);
+            }
+            let writer: &mut BufWriter<File> = writer.as_mut().unwrap();
+            let len: u32 = u32::try_from(record.len())
+                .map_err(|_| invalid_data(String::from("Record too large for a segment")))?;
+            writer.write_all(&len.to_be_bytes())?;
+            writer.write_all(&crc32fast::hash(record).to_be_bytes())?;
+            writer.write_all(record)?;
+            self.segments.last_mut().unwrap().size += record_len;
+            self.len += 1;
+        }
+        if let Some(mut writer) = writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Records in [start, end), clamped to the records that exist
+    pub fn read_range(&self, start: u64, end: u64) -> io::Result<Vec<Vec<u8>>> {
+        let end: u64 = end.min(self.len);
+        let mut records: Vec<Vec<u8>> = Vec::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            let segment_end: u64 = self.segments.get(i + 1).map_or(self.len, |next| next.base);
+            if segment_end <= start || segment.base >= end {
+                continue;
+            }
+            let mut index: u64 = segment.base;
+            let scan: SegmentScan =
+                SegmentLog::scan_segment(&segment.path, segment.base, |record| {
+                    if index >= start && index < end {
+                        records.push(record);
+                    }
+                    index += 1;
+                    Ok(())
+                })?;
+            if let Some(why) = scan.torn {
+                return Err(invalid_data(format!(
+                    "Segment {} is damaged: {why}",
+                    segment.path.display()
+                )));
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(segment) = self.segments.last() {
+            File::open(&segment.path)?.sync_all()?;
+        }
+        File::open(&self.dir)?.sync_all()
+    }
+
+    fn start_segment(&mut self) -> io::Result<BufWriter<File>> {
+        let path: PathBuf = self.dir.join(format!("{:020}.seg", self.len));
+        let mut writer = BufWriter::new(File::create_new(&path)?);
+        writer.write_all(SEGMENT_MAGIC)?;
+        writer.write_all(&[SEGMENT_VERSION])?;
+        writer.write_all(&self.len.to_be_bytes())?;
+        self.segments.push(Segment {
+            base: self.len,
+            path,
+            size: SEGMENT_HEADER_LEN,
+        });
+        Ok(writer)
+    }
+
+    fn open_active(&self) -> io::Result<BufWriter<File>> {
+        let segment: &Segment = self.segments.last().unwrap();
+        Ok(BufWriter::new(
+            File::options().append(true).open(&segment.path)?,
+        ))
+    }
+
+    // Calls visit with every intact record in the segment. A record that is
+    // cut short, or fails its checksum as the very last thing in the file,
+    // is what a crash mid write leaves behind and is reported as torn. A bad
+    // header or a damaged record with an intact one anywhere after it is
+    // real corruption and an InvalidData error naming where it was found.
+    // Lengths are only checked against the file, segment_bytes may have been
+    // different when the segment was written.
+    fn scan_segment<F>(path: &Path, base: u64, mut visit: F) -> io::Result<SegmentScan>
+    where
+        F: FnMut(Vec<u8>) -> io::Result<()>,
+    {
+        let file: File = File::open(path)?;
+        let file_len: u64 = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut scan = SegmentScan {
+            count: 0,
+            valid_len: 0,
+            torn: None,
+        };
+        let mut header = [0u8; SEGMENT_HEADER_LEN as usize];
+        if read_full(&mut reader, &mut header)? < header.len() {
+            scan.torn = Some(String::from("the segment header is incomplete"));
+            return Ok(scan);
+        }
+        if &header[..4] != SEGMENT_MAGIC {
+            return Err(invalid_data(format!(
+                "{} is not a log segment",
+                path.display()
+            )));
+        }
+        if header[4] != SEGMENT_VERSION {
+            return Err(invalid_data(format!(
+                "Segment {} has unsupported format version {}",
+                path.display(),
+                header[4]
+            )));
+        }
+        if u64::from_be_bytes(header[5..].try_into().unwrap()) != base {
+            return Err(invalid_data(format!(
+                "Segment {} header does not match its file name",
+                path.display()
+            )));
+        }
+        scan.valid_len = SEGMENT_HEADER_LEN;
+
+        loop {
+            let index: u64 = base + scan.count;
+            let mut record_header = [0u8; RECORD_HEADER_LEN as usize];
+            match read_full(&mut reader, &mut record_header)? {
+                0 => return Ok(scan),
+                n if n < record_header.len() => {
+                    scan.torn = Some(format!("record {index} is cut short in its header"));
+                    return Ok(scan);
+                }
+                _ => {}
+            }
+            let len: usize = u32::from_be_bytes(record_header[..4].try_into().unwrap()) as usize;
+            let crc: u32 = u32::from_be_bytes(record_header[4..].try_into().unwrap());
+            let left: u64 = file_len.saturating_sub(scan.valid_len + RECORD_HEADER_LEN);
+            if len as u64 > left {
+                // A crash only ever cuts short the last record, so running
+                // past the end is only a torn write if nothing intact follows
+                let mut rest: Vec<u8> = Vec::new();
+                reader.read_to_end(&mut rest)?;
+                if holds_intact_record(&rest) {
+                    return Err(invalid_data(format!(
+                        "Record {index} of segment {} has a damaged length",
+                        path.display()
+                    )));
+                }
+                scan.torn = Some(format!("record {index} is cut short"));
+                return Ok(scan);
+            }
+            let mut record: Vec<u8> = vec![0; len];
+            if read_full(&mut reader, &mut record)? < len {
+                scan.torn = Some(format!("record {index} is cut short"));
+                return Ok(scan);
+            }
+            if crc32fast::hash(&record) != crc {
+                if reader.fill_buf()?.is_empty() {
+                    scan.torn = Some(format!("record {index} fails its checksum"));
+                    return Ok(scan);
+                }
+                return Err(invalid_data(format!(
+                    "Checksum mismatch in record {index} of segment {}",
+                    path.display()
+                )));
+            }
+            visit(record)?;
+            scan.count += 1;
+            scan.valid_len += RECORD_HEADER_LEN + len as u64;
+        }
+    }
+}
+
+// What reading a segment front to back found
+struct SegmentScan {
+    count: u64,           // intact records
+    valid_len: u64,       // bytes up to the end of the last intact record
+    torn: Option<String>, // why the bytes after valid_len couldn't be read
+}
+
+// Whether an intact record starts anywhere in bytes. Records are never
+// empty, which keeps a run of zeroes from passing for one.
+fn holds_intact_record(bytes: &[u8]) -> bool {
+    (0..bytes.len()).any(|start| {
+        let header: &[u8] = match bytes.get(start..start + RECORD_HEADER_LEN as usize) {
+            None => return false,
+            Some(header) => header,
+        };
+        let len: usize = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc: u32 = u32::from_be_bytes(header[4..].try_into().unwrap());
+        let payload_start: usize = start + RECORD_HEADER_LEN as usize;
+        len > 0
+            && bytes
+                .get(payload_start..payload_start.saturating_add(len))
+                .is_some_and(|record| crc32fast::hash(record) == crc)
+    })
+}
+
+// Like read_exact but returns how much was read instead of failing at EOF
+fn read_full<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: Read,
+{
+    let mut read: usize = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        }
+    }
+    Ok(read)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Storage backend keeping typed entries in a SegmentLog
+pub struct SegmentedStorage<T> {
+    segments: SegmentLog,
+    _entry: PhantomData<T>,
+}
+
+impl<T> SegmentedStorage<T>
+where
+    T: Encodable + Decodable<T>,
+{
+    pub fn open(dir: String, segment_bytes: u64) -> io::Result<SegmentedStorage<T>> {
+        Ok(SegmentedStorage {
+            segments: SegmentLog::open(Path::new(&dir), segment_bytes)?,
+            _entry: PhantomData,
+        })
+    }
+}
+
+impl<T> Storage<T> for SegmentedStorage<T>
+where
+    T: Encodable + Decodable<T>,
+{
+    fn append(&mut self, entries: &[T]) -> io::Result<()> {
+        let records: Vec<String> = entries.iter().map(|entry| entry.encode()).collect();
+        self.segments.append(&records)
+    }
+
+    fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+        self.segments
+            .read_range(start as u64, end as u64)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, record)| {
+                String::from_utf8(record)
+                    .map_err(|_| invalid_data(format!("Record {} is not text", start + i)))
+            })
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.segments.len() as usize
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.segments.sync()
+    }
+
+    fn recovery_report(&self) -> RecoveryReport {
+        self.segments.recovery_report().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every test record is 8 bytes so record i starts at a known offset
+    const RECORD_BYTES: u64 = RECORD_HEADER_LEN + 8;
+
+    fn record_offset(i: u64) -> u64 {
+        SEGMENT_HEADER_LEN + i * RECORD_BYTES
+    }
+
+    // Fresh directory holding one synced segment of five records
+    fn segment_with_five(name: &str) -> (PathBuf, PathBuf) {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("graphlog-segment-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut log = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).unwrap();
+        let records: Vec<String> = (0..5).map(|i| format!("record-{i}")).collect();
+        log.append(&records).unwrap();
+        log.sync().unwrap();
+        let path: PathBuf = dir.join(format!("{:020}.seg", 0));
+        (dir, path)
+    }
+
+    fn overwrite(path: &Path, offset: u64, bytes: &[u8]) {
+        let mut data: Vec<u8> = fs::read(path).unwrap();
+        data[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+        fs::write(path, data).unwrap();
+    }
+
+    fn set_len(path: &Path, len: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+    }
+
+    #[test]
+    fn intact_segment_opens_unchanged() {
+        let (dir, _) = segment_with_five("intact");
+        let log = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).unwrap();
+        assert_eq!(log.len(), 5);
+        assert!(log.recovery_report().truncated.is_none());
+        assert_eq!(log.read_range(4, 5).unwrap(), vec![b"record-4".to_vec()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn segment_written_with_a_larger_segment_bytes_still_opens() {
+        let (dir, _) = segment_with_five("shrunk");
+        let segment_bytes: u64 = SEGMENT_HEADER_LEN + RECORD_BYTES;
+        let mut log = SegmentLog::open(&dir, segment_bytes).unwrap();
+        assert_eq!(log.len(), 5);
+        assert!(log.recovery_report().truncated.is_none());
+        assert_eq!(log.read_range(0, 5).unwrap().len(), 5);
+
+        // New records go into segments of the new size
+        log.append(&["record-5"]).unwrap();
+        log.sync().unwrap();
+        assert!(dir.join(format!("{:020}.seg", 5)).exists());
+        let log = SegmentLog::open(&dir, segment_bytes).unwrap();
+        assert_eq!(log.read_range(5, 6).unwrap(), vec![b"record-5".to_vec()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn record_cut_short_at_the_end_is_truncated() {
+        let (dir, path) = segment_with_five("cut-payload");
+        set_len(&path, record_offset(5) - 3);
+        let log = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(
+            log.recovery_report().truncated,
+            Some((path.clone(), RECORD_BYTES - 3))
+        );
+        assert_eq!(fs::metadata(&path).unwrap().len(), record_offset(4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn header_cut_short_at_the_end_is_truncated() {
+        let (dir, path) = segment_with_five("cut-header");
+        set_len(&path, record_offset(4) + 3);
+        let log = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(fs::metadata(&path).unwrap().len(), record_offset(4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_checksum_on_the_last_record_is_truncated() {
+        let (dir, path) = segment_with_five("last-checksum");
+        overwrite(&path, record_offset(4) + RECORD_HEADER_LEN, b"X");
+        let log = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).unwrap();
+        assert_eq!(log.len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_checksum_before_the_end_is_corruption() {
+        let (dir, path) = segment_with_five("mid-checksum");
+        overwrite(&path, record_offset(1) + RECORD_HEADER_LEN, b"X");
+        let err = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), record_offset(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_length_before_the_end_is_corruption() {
+        // Runs past the end of the file but fits in a segment, the records
+        // after it show it isn't a torn write
+        let (dir, path) = segment_with_five("mid-length");
+        overwrite(&path, record_offset(1), &1000u32.to_be_bytes());
+        let err = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), record_offset(5));
+
+        // Larger than any segment
+        overwrite(&path, record_offset(1), &u32::MAX.to_be_bytes());
+        let err = SegmentLog::open(&dir, DEFAULT_SEGMENT_BYTES).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), record_offset(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damage_in_an_earlier_segment_is_corruption() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("graphlog-segment-earlier-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // Two records per segment
+        let segment_bytes: u64 = SEGMENT_HEADER_LEN + 2 * RECORD_BYTES;
+        let mut log = SegmentLog::open(&dir, segment_bytes).unwrap();
+        let records: Vec<String> = (0..5).map(|i| format!("record-{i}")).collect();
+        log.append(&records).unwrap();
+        log.sync().unwrap();
+        let first: PathBuf = dir.join(format!("{:020}.seg", 0));
+        set_len(&first, record_offset(2) - 3);
+        let err = SegmentLog::open(&dir, segment_bytes).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,493 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use openssl::base64::{decode_block, encode_block};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::common::{Decodable, Encodable};
+use super::merkle::{leaf_hash, Hash};
+
+// Where a Log keeps its entries. The Log holds every entry in memory for
+// searching, a Storage only has to hand them back in order on startup and
+// keep whatever it is given.
+pub trait Storage<T> {
+    // Adds entries to the end of the store
+    fn append(&mut self, entries: &[T]) -> io::Result<()>;
+
+    // Entries in [start, end) exactly as they were stored, clamped to the
+    // entries that exist. These are the bytes the merkle leaves are hashed
+    // over, decoding and encoding an entry again is not guaranteed to give
+    // them back.
+    fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Makes everything appended so far durable
+    fn sync(&mut self) -> io::Result<()>;
+
+    // Leaf hashes of every stored entry if the backend keeps them, so the
+    // merkle tree doesn't have to be rehashed on startup
+    fn leaf_hashes(&self) -> Option<Vec<Hash>> {
+        None
+    }
+
+    // What had to be repaired when the storage was opened
+    fn recovery_report(&self) -> RecoveryReport {
+        RecoveryReport {
+            entries: self.len(),
+            ..RecoveryReport::default()
+        }
+    }
+}
+
+// When appended entries are fsynced. Append and batch lose nothing that was
+// acknowledged, periodic can lose up to the last interval of entries in a
+// crash in exchange for far fewer fsyncs. Serialized as its Display form so
+// config files read fsync = "periodic:1000".
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum FsyncPolicy {
+    // after every entry
+    Append,
+    // once after every batch of entries
+    #[default]
+    Batch,
+    // in the background every ms milliseconds
+    Periodic {
+        ms: u64,
+    },
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsyncPolicy::Append => write!(f, "append"),
+            FsyncPolicy::Batch => write!(f, "batch"),
+            FsyncPolicy::Periodic { ms } => write!(f, "periodic:{ms}"),
+        }
+    }
+}
+
+// Parses the Display form, e.g. "batch" or "periodic:1000"
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy.trim().split_once(':') {
+            None if policy.trim() == "append" => Ok(FsyncPolicy::Append),
+            None if policy.trim() == "batch" => Ok(FsyncPolicy::Batch),
+            Some(("periodic", ms)) => match ms.parse::<u64>() {
+                Ok(ms) if ms > 0 => Ok(FsyncPolicy::Periodic { ms }),
+                _ => Err(format!("Bad fsync interval {ms}, expected milliseconds")),
+            },
+            _ => Err(format!(
+                "Unknown fsync policy {policy}, expected append, batch or periodic:<ms>"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for FsyncPolicy {
+    type Error = String;
+
+    fn try_from(policy: String) -> Result<Self, Self::Error> {
+        policy.parse()
+    }
+}
+
+impl From<FsyncPolicy> for String {
+    fn from(policy: FsyncPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+// What opening a storage backend found and repaired
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub entries: usize,                    // entries recovered intact
+    pub truncated: Option<(PathBuf, u64)>, // file cut back to its last whole entry, bytes dropped
+    pub rebuilt_tree: bool,                // merkle leaf hashes had to be recomputed
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "recovered {} entries", self.entries)?;
+        if let Some((path, bytes)) = &self.truncated {
+            write!(
+                f,
+                ", dropped a torn write of {bytes} bytes from the end of {}",
+                path.display()
+            )?;
+        }
+        if self.rebuilt_tree {
+            write!(f, ", rebuilt the merkle tree")?;
+        }
+        Ok(())
+    }
+}
+
+// Leaf hash of a stored log entry in the merkle tree
+pub fn record_leaf_hash(record: &str) -> Hash {
+    leaf_hash(record.as_bytes())
+}
+
+// Keeps entries in a Vec, nothing survives a restart
+pub struct MemoryStorage<T> {
+    entries: Vec<T>,
+}
+
+impl<T> MemoryStorage<T> {
+    pub fn new() -> MemoryStorage<T> {
+        MemoryStorage {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for MemoryStorage<T> {
+    fn default() -> Self {
+        MemoryStorage::new()
+    }
+}
+
+impl<T> Storage<T> for MemoryStorage<T>
+where
+    T: Encodable + Clone,
+{
+    fn append(&mut self, entries: &[T]) -> io::Result<()> {
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+        let end: usize = end.min(self.entries.len());
+        let start: usize = start.min(end);
+        Ok(self.entries[start..end]
+            .iter()
+            .map(|entry| entry.encode())
+            .collect())
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Append only text file with one base64 encoded entry per line. The leaf
+// hashes of the merkle tree are kept next to it, one base64 hash per line,
+// e.g. reids.log -> reids.log.tree
+pub struct FileStorage<T> {
+    path: PathBuf,
+    len: usize,
+    report: RecoveryReport,
+    _entry: PhantomData<T>,
+}
+
+impl<T> FileStorage<T>
+where
+    T: Encodable + Decodable<T>,
+{
+    // Opens the file at path, creating it on the first append if it doesn't
+    // exist. A partial last line left by a crash is cut off, and the tree
+    // file is rewritten if it doesn't match the log.
+    pub fn open(path: String) -> io::Result<FileStorage<T>> {
+        let mut storage = FileStorage {
+            path: PathBuf::from(path),
+            len: 0,
+            report: RecoveryReport::default(),
+            _entry: PhantomData,
+        };
+        storage.truncate_torn_tail()?;
+        let records: Vec<String> = match File::open(&storage.path) {
+            Err(why) if why.kind() == io::ErrorKind::NotFound => {
+                println!("Couldn't open file: {why}");
+                Vec::new()
+            }
+            Err(why) => return Err(why),
+            Ok(_) => storage.read_lines(0, usize::MAX)?,
+        };
+        storage.len = records.len();
+        storage.report.entries = records.len();
+
+        if storage.leaf_hashes().is_none() {
+            println!("Rebuilding merkle tree file from log entries");
+            let leaves: Vec<Hash> = records
+                .iter()
+                .map(|record| record_leaf_hash(record))
+                .collect();
+            let _ = fs::remove_file(storage.tree_path());
+            FileStorage::<T>::write_tree(&storage.tree_path(), &leaves)?;
+            storage.report.rebuilt_tree = true;
+        }
+        Ok(storage)
+    }
+
+    // Every entry is written with its newline in one go so anything after
+    // the last newline is a write that never finished
+    fn truncate_torn_tail(&mut self) -> io::Result<()> {
+        let contents: Vec<u8> = match fs::read(&self.path) {
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(why) => return Err(why),
+            Ok(contents) => contents,
+        };
+        let valid_len: usize = contents
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |pos| pos + 1);
+        if valid_len < contents.len() {
+            println!(
+                "Truncating torn write at the end of {}",
+                self.path.display()
+            );
+            let file: File = File::options().write(true).open(&self.path)?;
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+            self.report.truncated = Some((self.path.clone(), (contents.len() - valid_len) as u64));
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_lines(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+        let file: File = match File::open(&self.path) {
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(why) => return Err(why),
+            Ok(file) => file,
+        };
+        BufReader::new(file)
+            .lines()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
+    }
+
+    fn tree_path(&self) -> PathBuf {
+        let mut tree_path = self.path.as_os_str().to_owned();
+        tree_path.push(".tree");
+        PathBuf::from(tree_path)
+    }
+
+    fn write_tree(tree_path: &Path, leaves: &[Hash]) -> io::Result<()> {
+        let mut file: File = File::options().append(true).create(true).open(tree_path)?;
+        for leaf in leaves {
+            writeln!(file, "{}", encode_block(leaf))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Storage<T> for FileStorage<T>
+where
+    T: Encodable + Decodable<T>,
+{
+    // Entries are written out whole or, if writing fails, the file is cut
+    // back to where it was before
+    fn append(&mut self, entries: &[T]) -> io::Result<()> {
+        let mut file: File = File::options().append(true).create(true).open(&self.path)?;
+        let size: u64 = file.metadata()?.len();
+        let records: Vec<String> = entries.iter().map(|entry| entry.encode()).collect();
+        let mut lines: String = String::new();
+        for record in &records {
+            lines.push_str(record);
+            lines.push('\n');
+        }
+        if let Err(why) = file.write_all(lines.as_bytes()) {
+            let _ = file.set_len(size);
+            return Err(why);
+        }
+        let leaves: Vec<Hash> = records
+            .iter()
+            .map(|record| record_leaf_hash(record))
+            .collect();
+        self.len += entries.len();
+        // The tree file is only a cache, it is rebuilt on open if it falls
+        // behind the log
+        if let Err(why) = FileStorage::<T>::write_tree(&self.tree_path(), &leaves) {
+            println!("Error persisting merkle tree: {why}");
+        }
+        Ok(())
+    }
+
+    fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+        self.read_lines(start, end.min(self.len))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        File::open(&self.path)?.sync_all()?;
+        File::open(self.tree_path())?.sync_all()
+    }
+
+    fn recovery_report(&self) -> RecoveryReport {
+        self.report.clone()
+    }
+
+    // None if the tree file is missing or does not cover the log
+    fn leaf_hashes(&self) -> Option<Vec<Hash>> {
+        let file: File = File::open(self.tree_path()).ok()?;
+        let mut leaves: Vec<Hash> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let leaf: Hash = decode_block(&line.ok()?).ok()?;
+            leaves.push(leaf);
+        }
+        if leaves.len() != self.len {
+            println!(
+                "Merkle tree file has {} leaves but log has {} entries",
+                leaves.len(),
+                self.len
+            );
+            return None;
+        }
+        Some(leaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stored as is, enough for storage which never looks inside an entry
+    #[derive(Clone, Debug, PartialEq)]
+    struct Line(String);
+
+    impl Encodable for Line {
+        fn encode(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    impl Decodable<Line> for Line {
+        fn decode(line: &str) -> Option<Line> {
+            Some(Line(line.to_string()))
+        }
+    }
+
+    fn lines(range: std::ops::Range<usize>) -> Vec<Line> {
+        range.map(|i| Line(format!("entry-{i}"))).collect()
+    }
+
+    // Fresh file holding three entries and their tree file
+    fn file_with_three(name: &str) -> PathBuf {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("graphlog-storage-{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut storage: FileStorage<Line> =
+            FileStorage::open(path.to_str().unwrap().to_string()).unwrap();
+        storage.append(&lines(0..3)).unwrap();
+        storage.sync().unwrap();
+        path
+    }
+
+    fn open(path: &Path) -> FileStorage<Line> {
+        FileStorage::open(path.to_str().unwrap().to_string()).unwrap()
+    }
+
+    fn expected_leaves(count: usize) -> Vec<Hash> {
+        lines(0..count)
+            .iter()
+            .map(|line| record_leaf_hash(&line.encode()))
+            .collect()
+    }
+
+    fn remove(path: &Path) {
+        let storage: FileStorage<Line> = open(path);
+        fs::remove_file(storage.tree_path()).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn memory_storage_clamps_reads() {
+        let mut storage: MemoryStorage<Line> = MemoryStorage::new();
+        storage.append(&lines(0..3)).unwrap();
+        assert_eq!(storage.len(), 3);
+        assert_eq!(storage.read_records(1, 2).unwrap(), vec!["entry-1"]);
+        assert_eq!(storage.read_records(2, 10).unwrap(), vec!["entry-2"]);
+        assert!(storage.read_records(5, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn intact_file_opens_without_repairs() {
+        let path: PathBuf = file_with_three("intact");
+        let storage: FileStorage<Line> = open(&path);
+        let report: RecoveryReport = storage.recovery_report();
+        assert_eq!(report.entries, 3);
+        assert!(report.truncated.is_none());
+        assert!(!report.rebuilt_tree);
+        assert_eq!(storage.leaf_hashes(), Some(expected_leaves(3)));
+        remove(&path);
+    }
+
+    #[test]
+    fn torn_tail_is_truncated() {
+        let path: PathBuf = file_with_three("torn");
+        let mut file: File = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"entry-3 never fin").unwrap();
+
+        let mut storage: FileStorage<Line> = open(&path);
+        let report: RecoveryReport = storage.recovery_report();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.truncated, Some((path.clone(), 17)));
+        assert!(fs::read(&path).unwrap().ends_with(b"entry-2\n"));
+        assert_eq!(
+            storage.read_records(0, 3).unwrap(),
+            vec!["entry-0", "entry-1", "entry-2"]
+        );
+
+        // The next append starts on a line of its own
+        storage.append(&lines(3..4)).unwrap();
+        let storage: FileStorage<Line> = open(&path);
+        assert_eq!(storage.len(), 4);
+        assert_eq!(storage.read_records(3, 4).unwrap(), vec!["entry-3"]);
+        assert_eq!(storage.leaf_hashes(), Some(expected_leaves(4)));
+        remove(&path);
+    }
+
+    #[test]
+    fn missing_tree_file_is_rebuilt() {
+        let path: PathBuf = file_with_three("missing-tree");
+        fs::remove_file(open(&path).tree_path()).unwrap();
+
+        let storage: FileStorage<Line> = open(&path);
+        assert!(storage.recovery_report().rebuilt_tree);
+        assert_eq!(storage.leaf_hashes(), Some(expected_leaves(3)));
+        remove(&path);
+    }
+
+    #[test]
+    fn stale_tree_file_is_rebuilt() {
+        let path: PathBuf = file_with_three("stale-tree");
+        // Tree file that fell behind the log, e.g. its write failed
+        let tree_path: PathBuf = open(&path).tree_path();
+        let tree: String = fs::read_to_string(&tree_path).unwrap();
+        fs::write(&tree_path, tree.lines().next().unwrap().to_string() + "\n").unwrap();
+        assert!(open(&path).recovery_report().rebuilt_tree);
+
+        let storage: FileStorage<Line> = open(&path);
+        assert!(!storage.recovery_report().rebuilt_tree);
+        assert_eq!(storage.leaf_hashes(), Some(expected_leaves(3)));
+        remove(&path);
+    }
+}
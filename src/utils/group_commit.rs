@@ -0,0 +1,234 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+use crate::types::common::{Encodable, Indexable};
+use crate::types::log::{Batch, Log, LogWriter};
+
+pub const DEFAULT_COMMIT_BATCH: usize = 256;
+pub const DEFAULT_COMMIT_DELAY_MS: u64 = 2;
+
+// How long appends are held so they can share a write and fsync
+#[derive(Debug, Clone, Copy)]
+pub struct GroupCommit {
+    pub max_batch: usize,    // a batch is written as soon as it is this large
+    pub max_delay: Duration, // otherwise this long after its first entry was queued
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        GroupCommit {
+            max_batch: DEFAULT_COMMIT_BATCH,
+            max_delay: Duration::from_millis(DEFAULT_COMMIT_DELAY_MS),
+        }
+    }
+}
+
+// Writes whatever is queued on log in batches, forever. There must only be
+// one of these per log since batches are committed in the order they are
+// taken. The lock on the log is only held to take a batch off the queue and
+// to add it to the log once it has been written, never while writing.
+pub async fn run_group_commit<T>(log: Arc<Mutex<Log<T>>>, config: GroupCommit)
+where
+    T: Encodable + Indexable + Clone + Send + 'static,
+{
+    let (queued_signal, writer): (Arc<Notify>, LogWriter<T>) = {
+        let log = log.lock().unwrap();
+        (log.queued_signal(), log.writer())
+    };
+    let max_batch: usize = config.max_batch.max(1);
+    loop {
+        queued_signal.notified().await;
+        let deadline: Instant = Instant::now() + config.max_delay;
+        while log.lock().unwrap().queued_len() < max_batch {
+            if timeout_at(deadline, queued_signal.notified())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        loop {
+            let batch: Batch<T> = log.lock().unwrap().take_batch(max_batch);
+            if batch.is_empty() {
+                break;
+            }
+            let writer: LogWriter<T> = writer.clone();
+            let written = tokio::task::spawn_blocking(move || {
+                let (written, result) = writer.write(batch.entries());
+                (batch, written, result)
+            })
+            .await;
+            match written {
+                // Nothing would answer the appends queued after this one
+                Err(why) => {
+                    println!("Group commit writer failed, aborting: {why}");
+                    std::process::abort();
+                }
+                Ok((batch, written, result)) => {
+                    if let Err(why) = &result {
+                        println!("Error writing batch of {} to log: {why}", batch.len());
+                    }
+                    log.lock().unwrap().commit_batch(batch, written, result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::time::Instant as StdInstant;
+
+    use tokio::sync::oneshot;
+    use tokio::time::timeout;
+
+    use crate::types::common::{Decodable, Id};
+    use crate::types::storage::{MemoryStorage, Storage};
+
+    // Long enough that nothing in these tests should ever wait for it
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    #[derive(Clone, Debug)]
+    struct Seq(u32);
+
+    impl Encodable for Seq {
+        fn encode(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    impl Decodable<Seq> for Seq {
+        fn decode(record: &str) -> Option<Seq> {
+            record.parse().ok().map(Seq)
+        }
+    }
+
+    impl Indexable for Seq {
+        fn index_ids(&self) -> Vec<Id> {
+            vec![vec![0; 32]]
+        }
+    }
+
+    // Memory storage that records the size of every write it gets, and
+    // fails them all once told to
+    struct CountingStorage {
+        entries: MemoryStorage<Seq>,
+        writes: Arc<Mutex<Vec<usize>>>,
+        fail: bool,
+    }
+
+    impl Storage<Seq> for CountingStorage {
+        fn append(&mut self, entries: &[Seq]) -> io::Result<()> {
+            self.writes.lock().unwrap().push(entries.len());
+            if self.fail {
+                return Err(io::Error::other("disk full"));
+            }
+            self.entries.append(entries)
+        }
+
+        fn read_records(&self, start: usize, end: usize) -> io::Result<Vec<String>> {
+            self.entries.read_records(start, end)
+        }
+
+        fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    type SharedLog = Arc<Mutex<Log<Seq>>>;
+    type Writes = Arc<Mutex<Vec<usize>>>;
+
+    // Log with a committer running on it, and the sizes of the writes it makes
+    fn committed_log(config: GroupCommit, fail: bool) -> (SharedLog, Writes) {
+        let writes: Writes = Arc::new(Mutex::new(Vec::new()));
+        let storage = CountingStorage {
+            entries: MemoryStorage::new(),
+            writes: writes.clone(),
+            fail,
+        };
+        let log: SharedLog = Arc::new(Mutex::new(Log::with_storage(Box::new(storage))));
+        tokio::spawn(run_group_commit(log.clone(), config));
+        (log, writes)
+    }
+
+    // Queues every seq under one lock, so the committer sees them all at once
+    fn queue(
+        log: &SharedLog,
+        seqs: std::ops::Range<u32>,
+    ) -> Vec<oneshot::Receiver<io::Result<()>>> {
+        let mut log = log.lock().unwrap();
+        seqs.map(|seq| log.queue(Seq(seq))).collect()
+    }
+
+    async fn results(committed: Vec<oneshot::Receiver<io::Result<()>>>) -> Vec<io::Result<()>> {
+        let mut results: Vec<io::Result<()>> = Vec::new();
+        for done in committed {
+            match timeout(PATIENCE, done).await {
+                Err(_) => panic!("Queued entry was never committed"),
+                Ok(result) => results.push(result.unwrap()),
+            }
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn full_batch_is_written_without_waiting_for_the_delay() {
+        let config = GroupCommit {
+            max_batch: 4,
+            max_delay: Duration::from_secs(3600),
+        };
+        let (log, writes) = committed_log(config, false);
+        let committed = queue(&log, 0..6);
+        for result in results(committed).await {
+            result.unwrap();
+        }
+        // The two left over go out right after the full batch
+        assert_eq!(*writes.lock().unwrap(), vec![4, 2]);
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 6);
+        assert_eq!(log.queued().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn partial_batch_is_written_after_the_delay() {
+        let config = GroupCommit {
+            max_batch: 100,
+            max_delay: Duration::from_millis(50),
+        };
+        let (log, writes) = committed_log(config, false);
+        let queued_at: StdInstant = StdInstant::now();
+        let committed = queue(&log, 0..3);
+        for result in results(committed).await {
+            result.unwrap();
+        }
+        assert!(queued_at.elapsed() >= config.max_delay);
+        assert_eq!(*writes.lock().unwrap(), vec![3]);
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_write_reaches_every_waiter() {
+        let (log, writes) = committed_log(GroupCommit::default(), true);
+        let root = log.lock().unwrap().root_hash();
+        let committed = queue(&log, 0..3);
+        for result in results(committed).await {
+            assert_eq!(result.unwrap_err().to_string(), "disk full");
+        }
+        assert_eq!(*writes.lock().unwrap(), vec![3]);
+        let log = log.lock().unwrap();
+        assert!(log.is_empty());
+        assert_eq!(log.root_hash(), root);
+        assert!(log.positions(&vec![0; 32]).is_empty());
+        assert_eq!(log.queued().count(), 0);
+    }
+}
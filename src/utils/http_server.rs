@@ -1,16 +1,197 @@
 use super::threadpool::ThreadPool;
+use crate::types::common::{AnchorType, Decodable};
+use crate::types::endorsement::Endorsement;
 use crate::types::log::Log;
+use crate::types::log_key::{verify_rotations, LogKeyRotation};
+use crate::types::merkle::{verify_consistency, verify_inclusion, Hash, SignedTreeHead};
 use crate::types::reid::Reid;
+use crate::types::revocation::EndorsementRevocation;
+use crate::types::storage::record_leaf_hash;
+use openssl::pkey::{Id, PKey, Public};
 use serde::{Deserialize, Serialize};
 use std::net::{TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
 
+pub type Handler = Arc<dyn Fn(TcpStream, Arc<Mutex<Log<Reid>>>) + Send + Sync + 'static>;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ReidMessage {
     pub reid: Reid,
     pub pub_key: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EndorsementMessage {
+    pub endorsement: Endorsement,
+    pub pub_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevocationMessage {
+    pub revocation: EndorsementRevocation,
+    pub pub_key: String,
+}
+
+// Reid together with the audit path proving it is in the log
+// under the included tree head. The reid is sent as the log stored it since
+// that is what its leaf is hashed over.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InclusionProofMessage {
+    pub index: u64,
+    pub record: String,
+    pub tree_head: SignedTreeHead,
+    pub audit_path: Vec<Hash>,
+}
+
+impl InclusionProofMessage {
+    pub fn reid(&self) -> Option<Reid> {
+        Reid::decode(&self.record)
+    }
+
+    // The tree head has to be signed by log_key, otherwise the log could
+    // make up a tree with any root it likes around the reid
+    pub fn verify(&self, log_key: &PKey<Public>) -> bool {
+        let leaf: Hash = record_leaf_hash(&self.record);
+        self.tree_head.verify_sig(log_key)
+            && verify_inclusion(
+                &leaf,
+                self.index as usize,
+                self.tree_head.tree_size as usize,
+                &self.audit_path,
+                &self.tree_head.root_hash,
+            )
+    }
+}
+
+// Every reid published for an id, oldest first, each with the audit path
+// proving it is in the log under tree_head. Versions appended after
+// tree_head have no audit path.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryMessage {
+    pub tree_head: SignedTreeHead,
+    pub versions: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryEntry {
+    pub index: u64,
+    pub record: String, // the reid as the log stored it
+    pub audit_path: Option<Vec<Hash>>,
+}
+
+impl HistoryEntry {
+    pub fn reid(&self) -> Option<Reid> {
+        Reid::decode(&self.record)
+    }
+
+    // False if there is no audit path to check
+    pub fn verify(&self, tree_head: &SignedTreeHead) -> bool {
+        match &self.audit_path {
+            None => false,
+            Some(audit_path) => verify_inclusion(
+                &record_leaf_hash(&self.record),
+                self.index as usize,
+                tree_head.tree_size as usize,
+                audit_path,
+                &tree_head.root_hash,
+            ),
+        }
+    }
+}
+
+// A page of log entries with their indices, each as the log stored it so
+// the page can be hashed back into the tree. next is where the following
+// page starts, None once the page reaches the end of the log under
+// tree_head.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RangeMessage {
+    pub tree_head: SignedTreeHead,
+    pub entries: Vec<(u64, String)>,
+    pub next: Option<u64>,
+}
+
+// Proof that the log at `old_size` is a prefix of the log under `tree_head`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConsistencyProofMessage {
+    pub old_size: u64,
+    pub tree_head: SignedTreeHead,
+    pub proof: Vec<Hash>,
+}
+
+impl ConsistencyProofMessage {
+    // old_root is the root the caller saw when the log had old_size entries
+    pub fn verify(&self, old_root: &[u8]) -> bool {
+        verify_consistency(
+            self.old_size as usize,
+            self.tree_head.tree_size as usize,
+            old_root,
+            &self.tree_head.root_hash,
+            &self.proof,
+        )
+    }
+}
+
+// The key the log signs tree heads with and every rotation that led to it,
+// oldest first, served at /.well-known/graphlog-log-key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogKeyMessage {
+    pub key_id: String,
+    pub pub_key: Vec<u8>, // raw Ed25519 public key
+    pub rotations: Vec<LogKeyRotation>,
+}
+
+impl LogKeyMessage {
+    pub fn public_key(&self) -> Option<PKey<Public>> {
+        PKey::public_key_from_raw_bytes(&self.pub_key, Id::ED25519).ok()
+    }
+
+    pub fn verify(&self) -> bool {
+        self.public_key().is_some() && verify_rotations(&self.rotations, &self.pub_key)
+    }
+
+    // Whether key signed for the log at some point, the current key or one it
+    // rotated away from
+    pub fn has_used(&self, key: &[u8]) -> bool {
+        self.pub_key == key
+            || self
+                .rotations
+                .iter()
+                .any(|rotation| rotation.get_old_key() == key)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PowMessage {
+    pub difficulty: u8,
+}
+
+// Base64 trust anchors to walk out from and how many endorsements deep to go
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReachMessage {
+    pub roots: Vec<String>,
+    pub hops: usize,
+}
+
+// Asks for the ids whose latest reid is live and asserts the anchor and
+// claims the key, at least one of the two has to be given
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchMessage {
+    pub anchor: Option<(AnchorType, String)>,
+    pub claim_key: Option<String>, // the key itself, the log matches on its fingerprint
+}
+
+impl SearchMessage {
+    // Index terms a matching reid has to have, see Reid::search_terms
+    pub fn terms(&self) -> Vec<String> {
+        let anchor = self
+            .anchor
+            .iter()
+            .map(|(anchor_type, value)| Reid::anchor_term(anchor_type, value));
+        let claim = self.claim_key.iter().map(|key| Reid::claim_term(key));
+        anchor.chain(claim).collect()
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct IdMessage {
     pub id_b64: String,
@@ -19,15 +200,11 @@ pub struct IdMessage {
 pub struct HttpServer {
     listener: TcpListener,
     pool: ThreadPool,
-    handler: Arc<dyn Fn(TcpStream, Arc<Mutex<Log<Reid>>>) + Send + Sync + 'static>,
+    handler: Handler,
 }
 
 impl HttpServer {
-    pub fn new(
-        addr_port: String,
-        num_threads: usize,
-        handler: Arc<dyn Fn(TcpStream, Arc<Mutex<Log<Reid>>>) + Send + Sync + 'static>,
-    ) -> HttpServer {
+    pub fn new(addr_port: String, num_threads: usize, handler: Handler) -> HttpServer {
         let listener = match TcpListener::bind(&addr_port) {
             Err(why) => panic!("Error binding to {addr_port}: {why}"),
             Ok(listener) => listener,
@@ -0,0 +1,81 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use openssl::base64::encode_block;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::sha::sha256;
+
+// Ed25519 key handling shared by keygen and the log server
+
+pub fn generate_ed25519() -> (PKey<Public>, PKey<Private>) {
+    let prv_key: PKey<Private> = PKey::generate_ed25519().unwrap();
+    let pub_key_raw: Vec<u8> = prv_key.raw_public_key().unwrap();
+    let pub_key: PKey<Public> = match PKey::public_key_from_raw_bytes(&pub_key_raw, Id::ED25519) {
+        Err(why) => panic!("Couldn't convert raw public key into Pkey<Public>: {why}"),
+        Ok(pub_key) => pub_key,
+    };
+    (pub_key, prv_key)
+}
+
+pub fn save_public_key(pub_key: &PKey<Public>, path: &Path) {
+    let puk_bytes: Vec<u8> = pub_key.public_key_to_pem().unwrap();
+    let mut pub_file = match File::create(path) {
+        Err(why) => panic!("couldn't create {}: {}", path.display(), why),
+        Ok(pub_file) => pub_file,
+    };
+    if let Err(why) = pub_file.write_all(&puk_bytes) {
+        panic!("couldn't write public key to {}: {why}", path.display());
+    }
+}
+
+// The key is written next to path and renamed over it so a crash never
+// leaves a half written key behind, only the owner can read it
+pub fn save_private_key(prv_key: &PKey<Private>, path: &Path) {
+    let prk_bytes: Vec<u8> = prv_key.private_key_to_pem_pkcs8().unwrap();
+    let tmp_path = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut prv_file = match options.open(&tmp_path) {
+        Err(why) => panic!("couldn't create {}: {}", tmp_path.display(), why),
+        Ok(prv_file) => prv_file,
+    };
+    if let Err(why) = prv_file
+        .write_all(&prk_bytes)
+        .and_then(|_| prv_file.sync_all())
+    {
+        panic!(
+            "couldn't write private key to {}: {why}",
+            tmp_path.display()
+        );
+    }
+    if let Err(why) = fs::rename(&tmp_path, path) {
+        panic!("couldn't move private key to {}: {why}", path.display());
+    }
+}
+
+pub fn load_private_key(path: &Path) -> PKey<Private> {
+    let pem: Vec<u8> = match fs::read(path) {
+        Err(why) => panic!("Couldn't read private key {}: {why}", path.display()),
+        Ok(pem) => pem,
+    };
+    match PKey::private_key_from_pem(&pem) {
+        Err(why) => panic!("Couldn't parse private key {}: {why}", path.display()),
+        Ok(prv_key) => prv_key,
+    }
+}
+
+// Raw Ed25519 public key of a private key
+pub fn raw_public_key(prv_key: &PKey<Private>) -> Vec<u8> {
+    match prv_key.raw_public_key() {
+        Err(why) => panic!("Couldn't get raw public key: {why}"),
+        Ok(pub_key_raw) => pub_key_raw,
+    }
+}
+
+// Short name for a raw public key, the base64 sha256 of its bytes
+pub fn key_id(pub_key_raw: &[u8]) -> String {
+    encode_block(&sha256(pub_key_raw))
+}
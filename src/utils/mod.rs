@@ -1,3 +1,5 @@
+pub mod group_commit;
 pub mod http_server;
+pub mod keys;
 // pub mod log_server;
 pub mod threadpool;